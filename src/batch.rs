@@ -0,0 +1,81 @@
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{Form, PronounSet, genderify_text, template_placeholders};
+use crate::protocol::{DispatchError, Status};
+
+/// The most texts one batch can have
+pub const MAX_BATCH_ITEMS: usize = 1000;
+
+/// One text in a batch. Names and sets left out use the batch's
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchItem {
+    pub text: String,
+    pub names: Option<Vec<String>>,
+    pub sets: Option<Vec<PronounSet>>
+}
+
+/// How one text went, with exactly one of `text` and `error`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>
+}
+
+impl From<Result<String, String>> for BatchResult {
+    fn from(result: Result<String, String>) -> Self {
+        match result {
+            Ok(text) => BatchResult { status: Status::Ok, text: Some(text), error: None },
+            Err(error) => BatchResult { status: Status::Error, text: None, error: Some(error) }
+        }
+    }
+}
+
+/// Like `genderify_text`, but placeholders it would leave unfilled for lack
+/// of names or sets are an error instead
+pub fn genderify_item(text: &str, names: &[String], sets: &[PronounSet]) -> Result<String, String> {
+    let (placeholders, _) = template_placeholders(text);
+    if placeholders.contains(&"name") && names.is_empty() {
+        return Err("This uses `[name]` but no names were given.".to_owned());
+    }
+    if placeholders.iter().any(|placeholder| Form::from_name(placeholder).is_some()) && sets.is_empty() {
+        return Err("This uses pronouns but no sets were given.".to_owned());
+    }
+    Ok(genderify_text(text, names.to_vec(), sets.to_vec()))
+}
+
+fn run_items(items: &[BatchItem], names: &[String], sets: &[PronounSet]) -> Vec<BatchResult> {
+    items.iter().map(|item| {
+        let names = item.names.as_deref().unwrap_or(names);
+        let sets = item.sets.as_deref().unwrap_or(sets);
+        genderify_item(&item.text, names, sets).into()
+    }).collect()
+}
+
+/// Fills in every text, in order. With `parallel` the items are split
+/// between the blocking threads, one chunk for each core
+pub async fn genderify_batch(items: Vec<BatchItem>, names: Vec<String>, sets: Vec<PronounSet>, parallel: bool) -> Result<Vec<BatchResult>, DispatchError> {
+    if items.len() > MAX_BATCH_ITEMS {
+        return Err(DispatchError::Invalid(format!("Batches can have at most {MAX_BATCH_ITEMS} texts.")));
+    }
+    let chunks = match parallel {
+        true => thread::available_parallelism().map_or(1, |cores| cores.get()),
+        false => 1
+    };
+    let chunk_size = items.len().div_ceil(chunks).max(1);
+
+    let mut tasks = Vec::new();
+    for chunk in items.chunks(chunk_size) {
+        let (chunk, names, sets) = (chunk.to_vec(), names.clone(), sets.clone());
+        tasks.push(tokio::task::spawn_blocking(move || run_items(&chunk, &names, &sets)));
+    }
+    let mut results = Vec::with_capacity(items.len());
+    for task in tasks {
+        results.extend(task.await.map_err(|error| DispatchError::Internal(error.to_string()))?);
+    }
+    Ok(results)
+}
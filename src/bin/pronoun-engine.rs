@@ -0,0 +1,228 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use clap::{Parser, Subcommand};
+
+use pronoun_engine::batch::genderify_item;
+use pronoun_engine::card::PronounCard;
+use pronoun_engine::client::{Client, ClientConfig, SentenceRequest};
+use pronoun_engine::config::Config;
+use pronoun_engine::engine::{Form, InferenceRules, InferredSet, PronounSet, parse_set_with};
+use pronoun_engine::formatters::{DiscordFormatter, PlainFormatter, SentenceFormatter};
+use pronoun_engine::protocol::{Command, Envelope, Response, Status};
+use pronoun_engine::sentences::{SelectionMode, TagFilter};
+
+#[derive(Debug, Parser)]
+#[command(name = "pronoun-engine", about = "Use the pronoun engine from the command line")]
+struct Args {
+    /// Print the result as JSON, in the same envelope the engine replies with
+    #[arg(long, global = true)]
+    json: bool,
+    /// Print Markdown instead of plain text, for results that have it
+    #[arg(long, global = true, conflicts_with = "json")]
+    markdown: bool,
+    /// The running engine's socket, for commands that need it. Defaults to
+    /// the one in the engine's config
+    #[arg(long, global = true)]
+    socket: Option<PathBuf>,
+    /// The engine's config file, for its socket and how it fills in sets
+    #[arg(long, global = true)]
+    config: Option<String>,
+    #[command(subcommand)]
+    command: CliCommand
+}
+
+#[derive(Debug, Subcommand)]
+enum CliCommand {
+    /// Parse a set and show every form, along with what had to be worked out
+    Parse {
+        set: String
+    },
+    /// Fill in a template file's placeholders, `-` reads it from stdin
+    Render {
+        file: String,
+        #[arg(long = "name", short)]
+        names: Vec<String>,
+        #[arg(long = "set", short)]
+        sets: Vec<String>
+    },
+    /// Show a pronoun card, with every form and a few examples
+    Card {
+        #[arg(long = "name", short)]
+        names: Vec<String>,
+        #[arg(long = "set", short, required = true)]
+        sets: Vec<String>
+    },
+    /// Ask the running engine for example sentences
+    Sentences {
+        #[arg(long = "name", short)]
+        names: Vec<String>,
+        #[arg(long = "set", short, required = true)]
+        sets: Vec<String>,
+        #[arg(long)]
+        count: Option<usize>,
+        /// A language tag like `pt-BR`
+        #[arg(long)]
+        locale: Option<String>,
+        #[arg(long, value_enum, default_value_t)]
+        mode: SelectionMode,
+        /// Only use sentences with one of these tags
+        #[arg(long = "include")]
+        include: Vec<String>,
+        /// Never use sentences with these tags
+        #[arg(long = "exclude")]
+        exclude: Vec<String>,
+        /// Packs to use sentences from as well
+        #[arg(long = "pack")]
+        packs: Vec<String>
+    },
+    /// Send any command to the running engine, written as JSON like
+    /// `{"name": "parse", "raw": "she/her"}`. Reads it from stdin if left out
+    Send {
+        command: Option<String>
+    }
+}
+
+fn parse_sets(sets: &[String], rules: &InferenceRules) -> Result<Vec<PronounSet>, String> {
+    sets.iter().map(|set| parse_set_with(set, rules).map(|parsed| parsed.set).map_err(|error| format!("{set}: {error}"))).collect()
+}
+
+fn read_input(file: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    match file {
+        "-" => std::io::stdin().read_to_string(&mut contents).map(|_| contents).map_err(|error| format!("stdin: {error}")),
+        _ => std::fs::read_to_string(file).map_err(|error| format!("{file}: {error}"))
+    }
+}
+
+fn describe_parsed(parsed: &InferredSet) -> String {
+    let number = match parsed.set.plural() {
+        true => "plural",
+        false => "singular"
+    };
+    let mut text = format!("{} ({number})", parsed.set.short());
+    for form in Form::ALL {
+        text += &format!("\n  {}: {}", form.label(), parsed.set.form(form));
+    }
+    if !parsed.inferred.is_empty() || parsed.plural_reason.is_some() {
+        text += "\n\nWorked out:";
+        for inferred in &parsed.inferred {
+            text += &format!("\n  {}: {}", inferred.form.label(), inferred.reason);
+        }
+        if let Some(reason) = &parsed.plural_reason {
+            text += &format!("\n  Plurality: {reason}");
+        }
+    }
+    text
+}
+
+/// How each reply looks to a person rather than a script
+fn describe(response: &Response, markdown: bool) -> String {
+    match response {
+        Response::Hello(handshake) => format!(
+            "{}, protocol version {} (understands {} to {})\nCommands: {}\nFeatures: {}",
+            handshake.server, handshake.version, handshake.min_version, handshake.version,
+            handshake.commands.join(", "), handshake.features.join(", ")
+        ),
+        Response::Genderified { text } => text.clone(),
+        Response::GenderifiedBatch { results } => results.iter().enumerate().map(|(i, result)| match result.status {
+            Status::Ok => format!("{}. {}", i + 1, result.text.as_deref().unwrap_or("")),
+            Status::Error => format!("{}. Error: {}", i + 1, result.error.as_deref().unwrap_or(""))
+        }).collect::<Vec<String>>().join("\n"),
+        Response::Sentences(generated) if markdown => DiscordFormatter::default().format(generated),
+        Response::Sentences(generated) => PlainFormatter::default().format(generated),
+        Response::Parsed { parsed, .. } => describe_parsed(parsed),
+        Response::Card(card) if markdown => card.render_markdown(),
+        Response::Card(card) => card.render_text(),
+        Response::Svg { svg } => svg.clone(),
+        Response::Submission(submission) => format!("#{} [{}] {}", submission.id, submission.status.name(), submission.text),
+        Response::Submissions { submissions } if submissions.is_empty() => "There are no submissions.".to_owned(),
+        Response::Submissions { submissions } => submissions.iter()
+            .map(|submission| format!("#{} [{}] {}", submission.id, submission.status.name(), submission.text))
+            .collect::<Vec<String>>().join("\n"),
+        Response::Packs { packs } if packs.is_empty() => "No packs are installed.".to_owned(),
+        Response::Packs { packs } => packs.iter()
+            .map(|manifest| format!("{} {} [{}] by {}, {}", manifest.name, manifest.version, manifest.language, manifest.author, manifest.license))
+            .collect::<Vec<String>>().join("\n"),
+        Response::Done => "Done.".to_owned()
+    }
+}
+
+async fn run(args: Args) -> Result<Response, String> {
+    // Sets are filled in the same way the engine would
+    let config = Config::load(args.config.as_deref())?;
+    let rules = &config.inference;
+    let client = || Client::new(args.socket.clone().unwrap_or_else(|| config.socket_path()), &ClientConfig::default());
+    match &args.command {
+        CliCommand::Parse { set } => {
+            let parsed = parse_set_with(set, rules)?;
+            Ok(Response::Parsed { short: parsed.set.short(), parsed })
+        }
+        CliCommand::Render { file, names, sets } => {
+            let template = read_input(file)?;
+            let text = genderify_item(&template, names, &parse_sets(sets, rules)?)?;
+            Ok(Response::Genderified { text })
+        }
+        CliCommand::Card { names, sets } => Ok(Response::Card(PronounCard::new(&parse_sets(sets, rules)?, names)?)),
+        CliCommand::Sentences { names, sets, count, locale, mode, include, exclude, packs } => {
+            let filter = match include.is_empty() && exclude.is_empty() {
+                true => None,
+                false => Some(TagFilter { include: include.clone(), exclude: exclude.clone() })
+            };
+            let request = SentenceRequest {
+                filter,
+                packs: (!packs.is_empty()).then(|| packs.clone()),
+                mode: *mode,
+                count: *count,
+                locale: locale.clone(),
+                ..Default::default()
+            };
+            let generated = client().sentences(names.clone(), parse_sets(sets, rules)?, request).await?;
+            Ok(Response::Sentences(generated))
+        }
+        CliCommand::Send { command } => {
+            let command = match command {
+                Some(command) => command.clone(),
+                None => read_input("-")?
+            };
+            let command: Command = serde_json::from_str(&command).map_err(|error| format!("Invalid command: {error}"))?;
+            client().send(command).await
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let now = Instant::now();
+    let args = Args::parse();
+    let json = args.json;
+    let markdown = args.markdown;
+
+    let output = match run(args).await {
+        // Errors are JSON too, so scripts only have one shape to read
+        result if json => {
+            let failed = result.is_err();
+            match serde_json::to_string_pretty(&Envelope::new(None, result, now)) {
+                Ok(envelope) if failed => {
+                    let _ = writeln!(std::io::stdout(), "{envelope}");
+                    std::process::exit(1);
+                }
+                envelope => envelope.map_err(|error| error.to_string())
+            }
+        }
+        // Templates usually end with a newline of their own
+        Ok(response) => Ok(describe(&response, markdown).trim_end_matches('\n').to_owned()),
+        Err(error) => Err(error)
+    };
+    match output {
+        // Whatever it's piped into might stop reading early, like `head`
+        Ok(output) => {
+            let _ = writeln!(std::io::stdout(), "{output}");
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{Form, PronounSet, genderify_text};
+
+// Between them these use every form and both verb agreements
+const EXAMPLES: [&str; 5] = [
+    "[^subjective] {is|are} going to the park today.",
+    "I saw [objective] at the shops yesterday.",
+    "That's [possessive] coat on the chair.",
+    "I don't have a pen, so I borrowed [possessive2].",
+    "[^subjective] {thinks|think} highly of [reflexive]."
+];
+const NAME_EXAMPLE: &str = "This is [name], and [subjective] {has|have} been here a while.";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CardForm {
+    pub form: Form,
+    pub label: String,
+    pub value: String
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CardSet {
+    pub short: String,
+    pub plural: bool,
+    pub forms: Vec<CardForm>,
+    pub examples: Vec<String>
+}
+
+/// Everything needed to show someone's pronouns in use, like a reference sheet
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PronounCard {
+    pub title: String,
+    pub names: Vec<String>,
+    pub sets: Vec<CardSet>
+}
+
+impl PronounCard {
+    pub fn new(sets: &[PronounSet], names: &[String]) -> Result<PronounCard, &'static str> {
+        if sets.is_empty() {
+            return Err("A pronoun card needs at least one pronoun set.");
+        }
+
+        let shorts: Vec<String> = sets.iter().map(|set| set.short()).collect();
+        let title = match names.is_empty() {
+            true => shorts.join(" or "),
+            false => format!("{} ({})", names.join(" or "), shorts.join(" or "))
+        };
+
+        // Only use the first name so the card comes out the same every time
+        let example_names: Vec<String> = names.iter().take(1).cloned().collect();
+        let card_sets = sets.iter().zip(shorts).map(|(set, short)| {
+            let mut templates: Vec<&str> = EXAMPLES.to_vec();
+            if !example_names.is_empty() {
+                templates.insert(0, NAME_EXAMPLE);
+            }
+            CardSet {
+                short,
+                plural: set.plural(),
+                forms: Form::ALL.iter().map(|form| CardForm {
+                    form: *form,
+                    label: form.label().to_owned(),
+                    value: set.form(*form).to_owned()
+                }).collect(),
+                examples: templates.iter()
+                    .map(|template| genderify_text(template, example_names.clone(), vec![set.clone()]))
+                    .collect()
+            }
+        }).collect();
+
+        Ok(PronounCard {
+            title,
+            names: names.to_vec(),
+            sets: card_sets
+        })
+    }
+
+    pub fn render_text(&self) -> String {
+        let mut text = self.title.clone();
+        for set in &self.sets {
+            text += &format!("\n\n{}\n", set.short);
+            for form in &set.forms {
+                text += &format!("\n  {}: {}", form.label, form.value);
+            }
+            text += "\n";
+            for example in &set.examples {
+                text += &format!("\n  - {example}");
+            }
+        }
+        text
+    }
+
+    pub fn render_markdown(&self) -> String {
+        let mut text = format!("# {}", escape_markdown(&self.title));
+        for set in &self.sets {
+            text += &format!("\n\n## {}\n\n| Form | Pronoun |\n| --- | --- |", escape_markdown(&set.short));
+            for form in &set.forms {
+                text += &format!("\n| {} | {} |", escape_markdown(&form.label), escape_markdown(&form.value));
+            }
+            text += "\n";
+            for example in &set.examples {
+                text += &format!("\n- {}", escape_markdown(example));
+            }
+        }
+        text
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+    HighContrast
+}
+
+struct Palette {
+    background: &'static str,
+    text: &'static str,
+    muted: &'static str,
+    accent: &'static str,
+    rule: &'static str
+}
+
+impl Theme {
+    fn palette(&self) -> Palette {
+        match self {
+            Theme::Light => Palette {
+                background: "#ffffff",
+                text: "#1f2328",
+                muted: "#59636e",
+                accent: "#8250df",
+                rule: "#d1d9e0"
+            },
+            Theme::Dark => Palette {
+                background: "#1e1f22",
+                text: "#f2f3f5",
+                muted: "#b5bac1",
+                accent: "#c9a7ff",
+                rule: "#3f4147"
+            },
+            Theme::HighContrast => Palette {
+                background: "#000000",
+                text: "#ffffff",
+                muted: "#ffffff",
+                accent: "#ffff00",
+                rule: "#ffffff"
+            }
+        }
+    }
+}
+
+// Names and forms can have anything in them, `a|b` would split a table cell
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        if matches!(char, '\\' | '`' | '*' | '_' | '~' | '|' | '[' | ']' | '<' | '>' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+// Everything user-supplied goes through this before going into the SVG
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            _ => escaped.push(char)
+        }
+    }
+    escaped
+}
+
+const SVG_WIDTH: u32 = 640;
+const SVG_PADDING: u32 = 24;
+const LINE_HEIGHT: u32 = 24;
+// Where form values start, after the labels
+const VALUE_OFFSET: u32 = 220;
+const BULLET_INDENT: u32 = 14;
+
+// A rough width for sans-serif text. There's no font to measure with, so this
+// errs on the wide side
+fn text_width(text: &str, font_size: u32) -> u32 {
+    let ems: f64 = text.chars().map(|char| match char {
+        'i' | 'j' | 'l' | 'I' | '.' | ',' | '\'' | '|' | '!' | ' ' => 0.3,
+        'm' | 'w' | 'M' | 'W' => 0.9,
+        char if char.is_ascii() => 0.62,
+        // Emoji and CJK are about a full em
+        _ => 1.0
+    }).sum();
+    (ems * font_size as f64).ceil() as u32
+}
+
+/// Splits text into lines no wider than `width`, between words where it can
+/// and inside them where a word is too wide on its own
+fn wrap(text: &str, font_size: u32, width: u32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        let joined = match line.is_empty() {
+            true => word.to_owned(),
+            false => format!("{line} {word}")
+        };
+        if text_width(&joined, font_size) <= width {
+            line = joined;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        for char in word.chars() {
+            if !line.is_empty() && text_width(&format!("{line}{char}"), font_size) > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            line.push(char);
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+impl PronounCard {
+    /// A self-contained SVG with no external fonts or images so it can be
+    /// attached or embedded anywhere
+    pub fn render_svg(&self, theme: Theme) -> String {
+        let palette = theme.palette();
+        let content_width = SVG_WIDTH - 2 * SVG_PADDING;
+        let mut body = String::new();
+        let mut y = SVG_PADDING;
+
+        for (i, line) in wrap(&self.title, 26, content_width).iter().enumerate() {
+            y += if i == 0 { 28 } else { 32 };
+            body += &format!(
+                "<text x=\"{SVG_PADDING}\" y=\"{y}\" font-size=\"26\" font-weight=\"bold\" fill=\"{}\">{}</text>",
+                palette.text, escape_xml(line)
+            );
+        }
+        y += 16;
+
+        for set in &self.sets {
+            for (i, line) in wrap(&set.short, 20, content_width).iter().enumerate() {
+                y += if i == 0 { LINE_HEIGHT + 8 } else { LINE_HEIGHT };
+                body += &format!(
+                    "<text x=\"{SVG_PADDING}\" y=\"{y}\" font-size=\"20\" font-weight=\"bold\" fill=\"{}\">{}</text>",
+                    palette.accent, escape_xml(line)
+                );
+            }
+            for form in &set.forms {
+                y += LINE_HEIGHT;
+                body += &format!(
+                    "<line x1=\"{SVG_PADDING}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"/>",
+                    y - 17, SVG_WIDTH - SVG_PADDING, y - 17, palette.rule
+                );
+                body += &format!(
+                    "<text x=\"{SVG_PADDING}\" y=\"{y}\" font-size=\"15\" fill=\"{}\">{}</text>",
+                    palette.muted, escape_xml(&form.label)
+                );
+                for (i, line) in wrap(&form.value, 15, content_width - VALUE_OFFSET).iter().enumerate() {
+                    if i > 0 {
+                        y += LINE_HEIGHT;
+                    }
+                    body += &format!(
+                        "<text x=\"{}\" y=\"{y}\" font-size=\"15\" font-weight=\"bold\" fill=\"{}\">{}</text>",
+                        SVG_PADDING + VALUE_OFFSET, palette.text, escape_xml(line)
+                    );
+                }
+            }
+            y += 8;
+            for example in &set.examples {
+                // Lines after the first line up with the text, not the bullet
+                for (i, line) in wrap(example, 15, content_width - BULLET_INDENT).iter().enumerate() {
+                    y += LINE_HEIGHT;
+                    let (x, bullet) = match i {
+                        0 => (SVG_PADDING, "• "),
+                        _ => (SVG_PADDING + BULLET_INDENT, "")
+                    };
+                    body += &format!(
+                        "<text x=\"{x}\" y=\"{y}\" font-size=\"15\" fill=\"{}\">{bullet}{}</text>",
+                        palette.text, escape_xml(line)
+                    );
+                }
+            }
+        }
+
+        let height = y + SVG_PADDING;
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{SVG_WIDTH}\" height=\"{height}\" viewBox=\"0 0 {SVG_WIDTH} {height}\" font-family=\"sans-serif\">\
+            <title>{}</title>\
+            <rect width=\"100%\" height=\"100%\" rx=\"12\" fill=\"{}\"/>{body}</svg>",
+            escape_xml(&self.title), palette.background
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parse_set;
+
+    #[test]
+    fn examples_use_every_form() {
+        let card = PronounCard::new(&[parse_set("xe/xem/xyr/xyrs/xemself").unwrap()], &[]).unwrap();
+        let examples = card.sets[0].examples.join(" ");
+        for form in ["Xe", "xem", "xyr", "xyrs", "xemself"] {
+            assert!(examples.contains(form), "{form} in {examples}");
+        }
+        assert_eq!(card.sets[0].forms.len(), 5);
+        assert_eq!(card.title, "xe/xem/xyr");
+    }
+
+    #[test]
+    fn verbs_agree_with_plurality() {
+        let names = ["Ada".to_owned(), "Grace".to_owned()];
+        let card = PronounCard::new(&[parse_set("she/her").unwrap(), parse_set("they/them").unwrap()], &names).unwrap();
+        assert_eq!(card.title, "Ada or Grace (she/her or they/them)");
+        let [she, they] = [&card.sets[0], &card.sets[1]];
+        assert!(!she.plural && they.plural);
+        assert_eq!(she.examples[0], "This is Ada, and she has been here a while.");
+        assert_eq!(she.examples[1], "She is going to the park today.");
+        assert_eq!(she.examples[5], "She thinks highly of herself.");
+        assert_eq!(they.examples[0], "This is Ada, and they have been here a while.");
+        assert_eq!(they.examples[1], "They are going to the park today.");
+        assert_eq!(they.examples[5], "They think highly of themself.");
+    }
+
+    #[test]
+    fn markdown_is_escaped() {
+        let card = PronounCard::new(&[parse_set("a|b/c*/d_").unwrap()], &["[Ada](x)".to_owned()]).unwrap();
+        let markdown = card.render_markdown();
+        assert!(markdown.starts_with("# \\[Ada\\](x) ("), "{markdown}");
+        assert!(markdown.contains("\n| Subjective | a\\|b |\n"), "{markdown}");
+        assert!(markdown.contains("| c\\* |") && markdown.contains("| d\\_ |"), "{markdown}");
+        // Every row still has exactly its two cells
+        for row in markdown.lines().filter(|line| line.starts_with('|')) {
+            assert_eq!(row.replace("\\|", "").matches('|').count(), 3, "{row}");
+        }
+    }
+
+    #[test]
+    fn wrapping_fits_the_width() {
+        let text = "Bartholomew-Maximilian Featherstonehaugh-Cholmondeley and friends";
+        let lines = wrap(text, 26, 300);
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| text_width(line, 26) <= 300), "{lines:?}");
+        assert_eq!(lines.join(" ").replace(' ', ""), text.replace(' ', ""));
+
+        let lines = wrap(&"x".repeat(200), 15, 100);
+        assert!(lines.len() > 1 && lines.iter().all(|line| text_width(line, 15) <= 100));
+        assert_eq!(wrap("she/her", 20, 600), vec!["she/her"]);
+    }
+
+    #[test]
+    fn long_cards_grow_instead_of_overflowing() {
+        let set = parse_set("she/her").unwrap();
+        let short = PronounCard::new(std::slice::from_ref(&set), &["Ada".to_owned()]).unwrap().render_svg(Theme::Light);
+        let long = PronounCard::new(&[set], &["Ada ".repeat(40)]).unwrap().render_svg(Theme::Light);
+        let height = |svg: &str| svg.split("height=\"").nth(1).and_then(|rest| rest.split('"').next()).and_then(|height| height.parse::<u32>().ok()).unwrap();
+        assert!(height(&long) > height(&short));
+    }
+}
@@ -0,0 +1,259 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{self, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+use crate::batch::{BatchItem, BatchResult};
+use crate::card::{PronounCard, Theme};
+use crate::engine::{InferredSet, PronounSet};
+use crate::framing::{Frame, MAX_FRAME_SIZE, read_frame, write_frame};
+use crate::packs::PackManifest;
+use crate::protocol::{Command, Envelope, Handshake, PROTOCOL_VERSION, Request, RequestId, Response, Status};
+use crate::sentences::{GeneratedSentences, SelectionMode, TagFilter};
+use crate::submissions::{Review, Submission, SubmissionStatus};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// How long a request can take, counting connecting if it has to
+    pub timeout_seconds: u64,
+    /// Connections open at once, requests past this wait for one to be free
+    pub pool_size: usize
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            timeout_seconds: 10,
+            pool_size: 4
+        }
+    }
+}
+
+/// The optional parts of a `sentences` request, left out ones use the
+/// engine's defaults
+#[derive(Clone, Debug, Default)]
+pub struct SentenceRequest {
+    pub filter: Option<TagFilter>,
+    pub packs: Option<Vec<String>>,
+    pub guild: Option<String>,
+    pub user: Option<String>,
+    pub mode: SelectionMode,
+    pub count: Option<usize>,
+    pub locale: Option<String>,
+    pub intro: Option<String>,
+    pub outro: Option<String>
+}
+
+// One socket that's already said hello. It only ever has one request
+// waiting, so the next reply is always for that request
+struct Connection {
+    stream: BufReader<UnixStream>
+}
+
+impl Connection {
+    async fn open(socket: &Path) -> io::Result<Connection> {
+        let mut connection = Connection { stream: BufReader::new(UnixStream::connect(socket).await?) };
+        let hello = Request { request_id: None, command: Command::Hello { version: PROTOCOL_VERSION, features: Vec::new() } };
+        let envelope = connection.call(&hello).await?;
+        match envelope.status {
+            Status::Ok => Ok(connection),
+            // An engine too old or too new, trying again won't help
+            Status::Error => Err(io::Error::new(io::ErrorKind::Unsupported, envelope.error.unwrap_or_default()))
+        }
+    }
+
+    async fn call(&mut self, request: &Request) -> io::Result<Envelope> {
+        self.write(request).await?;
+        self.read(request).await
+    }
+
+    async fn write(&mut self, request: &Request) -> io::Result<()> {
+        write_frame(self.stream.get_mut(), request).await
+    }
+
+    async fn read(&mut self, request: &Request) -> io::Result<Envelope> {
+        let data = match read_frame(&mut self.stream, MAX_FRAME_SIZE).await? {
+            Some(Frame::Message(data)) => data,
+            Some(Frame::TooLong) => return Err(io::Error::new(io::ErrorKind::InvalidData, "The reply was too long")),
+            None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The engine closed the connection"))
+        };
+        let envelope: Envelope = serde_json::from_slice(&data).map_err(io::Error::from)?;
+        if envelope.request_id != request.request_id {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "The reply was for a different request"));
+        }
+        Ok(envelope)
+    }
+}
+
+/// Talks to a running engine over its socket. Cloning is cheap and clones
+/// share the same connections
+#[derive(Clone)]
+pub struct Client {
+    socket: Arc<PathBuf>,
+    idle: Arc<Mutex<Vec<Connection>>>,
+    slots: Arc<Semaphore>,
+    next_id: Arc<AtomicU64>,
+    timeout: Duration
+}
+
+impl Client {
+    /// Doesn't connect until the first request
+    pub fn new(socket: impl Into<PathBuf>, config: &ClientConfig) -> Client {
+        Client {
+            socket: Arc::new(socket.into()),
+            idle: Arc::new(Mutex::new(Vec::new())),
+            slots: Arc::new(Semaphore::new(config.pool_size.max(1))),
+            next_id: Arc::new(AtomicU64::new(1)),
+            timeout: Duration::from_secs(config.timeout_seconds)
+        }
+    }
+
+    /// Sends any command and waits for its reply. A pooled connection that
+    /// turns out to be closed, like after the engine restarts, is replaced and
+    /// the request sent again once. That only happens if the request couldn't
+    /// be sent at all, so nothing is ever done twice
+    pub async fn send(&self, command: Command) -> Result<Response, String> {
+        let _slot = self.slots.acquire().await.map_err(|error| error.to_string())?;
+        let request = Request {
+            request_id: Some(RequestId::Number(self.next_id.fetch_add(1, Ordering::Relaxed))),
+            command
+        };
+        let envelope = match timeout(self.timeout, self.call(&request)).await {
+            Ok(result) => result.map_err(|error| format!("Couldn't reach the engine: {error}"))?,
+            Err(_) => return Err(format!("The engine didn't reply within {} seconds", self.timeout.as_secs()))
+        };
+        match envelope.status {
+            Status::Ok => envelope.result.ok_or_else(|| "The engine's reply was empty".to_owned()),
+            Status::Error => Err(envelope.error.unwrap_or_else(|| "The engine didn't say what went wrong".to_owned()))
+        }
+    }
+
+    // Connections that fail or time out are dropped rather than put back, so
+    // a late reply can't be mistaken for the next request's
+    async fn call(&self, request: &Request) -> io::Result<Envelope> {
+        let pooled = self.idle.lock().unwrap().pop();
+        let (connection, result) = match pooled {
+            Some(mut connection) => match connection.write(request).await {
+                Ok(()) => {
+                    let result = connection.read(request).await;
+                    (connection, result)
+                }
+                Err(_) => {
+                    let mut connection = Connection::open(&self.socket).await?;
+                    let result = connection.call(request).await;
+                    (connection, result)
+                }
+            },
+            None => {
+                let mut connection = Connection::open(&self.socket).await?;
+                let result = connection.call(request).await;
+                (connection, result)
+            }
+        };
+        let envelope = result?;
+        self.idle.lock().unwrap().push(connection);
+        Ok(envelope)
+    }
+
+    /// What the engine supports
+    pub async fn hello(&self, features: Vec<String>) -> Result<Handshake, String> {
+        match self.send(Command::Hello { version: PROTOCOL_VERSION, features }).await? {
+            Response::Hello(handshake) => Ok(handshake),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn genderify(&self, text: &str, names: Vec<String>, sets: Vec<PronounSet>) -> Result<String, String> {
+        match self.send(Command::Genderify { text: text.to_owned(), names, sets }).await? {
+            Response::Genderified { text } => Ok(text),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn genderify_batch(&self, items: Vec<BatchItem>, names: Vec<String>, sets: Vec<PronounSet>, parallel: bool) -> Result<Vec<BatchResult>, String> {
+        match self.send(Command::GenderifyBatch { items, names, sets, parallel }).await? {
+            Response::GenderifiedBatch { results } => Ok(results),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn sentences(&self, names: Vec<String>, sets: Vec<PronounSet>, request: SentenceRequest) -> Result<GeneratedSentences, String> {
+        let SentenceRequest { filter, packs, guild, user, mode, count, locale, intro, outro } = request;
+        match self.send(Command::Sentences { names, sets, filter, packs, guild, user, mode, count, locale, intro, outro }).await? {
+            Response::Sentences(generated) => Ok(generated),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn parse(&self, raw: &str) -> Result<InferredSet, String> {
+        match self.send(Command::Parse { raw: raw.to_owned() }).await? {
+            Response::Parsed { parsed, .. } => Ok(parsed),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn card(&self, names: Vec<String>, sets: Vec<PronounSet>) -> Result<PronounCard, String> {
+        match self.send(Command::Card { names, sets }).await? {
+            Response::Card(card) => Ok(card),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn card_svg(&self, names: Vec<String>, sets: Vec<PronounSet>, theme: Theme) -> Result<String, String> {
+        match self.send(Command::CardSvg { names, sets, theme }).await? {
+            Response::Svg { svg } => Ok(svg),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn set_guild_filter(&self, guild: &str, filter: Option<TagFilter>) -> Result<(), String> {
+        self.send(Command::GuildFilter { guild: guild.to_owned(), filter }).await.map(|_| ())
+    }
+
+    pub async fn set_guild_packs(&self, guild: &str, packs: Option<Vec<String>>) -> Result<(), String> {
+        self.send(Command::GuildPacks { guild: guild.to_owned(), packs }).await.map(|_| ())
+    }
+
+    pub async fn packs(&self) -> Result<Vec<PackManifest>, String> {
+        match self.send(Command::Packs).await? {
+            Response::Packs { packs } => Ok(packs),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn refresh_sentences(&self) -> Result<(), String> {
+        self.send(Command::RefreshSentences).await.map(|_| ())
+    }
+
+    pub async fn submit(&self, text: &str, tags: Vec<String>, language: Option<String>, submitter: &str) -> Result<Submission, String> {
+        match self.send(Command::Submit { text: text.to_owned(), tags, language, submitter: submitter.to_owned() }).await? {
+            Response::Submission(submission) => Ok(submission),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn review(&self, id: u64, moderator: &str, review: Review) -> Result<Submission, String> {
+        match self.send(Command::Review { id, moderator: moderator.to_owned(), review }).await? {
+            Response::Submission(submission) => Ok(submission),
+            _ => Err(unexpected())
+        }
+    }
+
+    pub async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String> {
+        match self.send(Command::Submissions { status }).await? {
+            Response::Submissions { submissions } => Ok(submissions),
+            _ => Err(unexpected())
+        }
+    }
+}
+
+fn unexpected() -> String {
+    "The engine sent back the wrong kind of reply".to_owned()
+}
@@ -1 +1,2 @@
+pub mod submit;
 pub mod try_set;
\ No newline at end of file
@@ -0,0 +1,66 @@
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::{ApplicationCommandInteraction, CommandDataOptionValue};
+use serenity::prelude::Context;
+use serenity::prelude::SerenityError;
+use serenity::utils::Colour;
+
+use crate::client::Client;
+
+pub async fn run(interaction: &ApplicationCommandInteraction, ctx: &Context, client: &Client) -> Result<(), SerenityError> {
+    let mut text = "";
+    let mut tags = Vec::new();
+    let mut language = None;
+    for option in &interaction.data.options {
+        match (option.name.as_str(), option.resolved.as_ref()) {
+            ("sentence", Some(CommandDataOptionValue::String(value))) => text = value,
+            ("tags", Some(CommandDataOptionValue::String(value))) => {
+                tags = value.split(',').filter(|tag| !tag.trim().is_empty()).map(|tag| tag.to_owned()).collect();
+            }
+            ("language", Some(CommandDataOptionValue::String(value))) => language = Some(value.clone()),
+            _ => ()
+        }
+    }
+
+    let (description, colour) = match client.submit(text, tags, language, &interaction.user.id.to_string()).await {
+        Ok(submission) => (
+            format!("Thanks! Your sentence is submission #{} and will show up once a moderator approves it.", submission.id),
+            Colour::from_rgb(0, 200, 83)
+        ),
+        Err(error) => (error, Colour::from_rgb(255, 0, 0))
+    };
+    interaction.create_interaction_response(&ctx.http,
+        |r| r.interaction_response_data(|m|
+            m
+            .ephemeral(true)
+            .embed(|e|
+                e.description(description).colour(colour)
+            )
+        )
+    ).await
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("submit-sentence").description("Suggest a new sentence for everyone to practise with!")
+    .create_option(|option| {
+        option
+            .name("sentence")
+            .description("The sentence, using placeholders like [name] and [subjective]")
+            .kind(CommandOptionType::String)
+            .required(true)
+    })
+    .create_option(|option| {
+        option
+            .name("tags")
+            .description("Comma-separated tags, like work or school")
+            .kind(CommandOptionType::String)
+            .required(false)
+    })
+    .create_option(|option| {
+        option
+            .name("language")
+            .description("The sentence's language, like en or pt-BR (English if left out)")
+            .kind(CommandOptionType::String)
+            .required(false)
+    })
+}
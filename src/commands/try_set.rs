@@ -1,4 +1,3 @@
-use mysql_async::Conn;
 use serenity::builder::CreateApplicationCommand;
 use serenity::model::prelude::command::CommandOptionType;
 use serenity::model::prelude::interaction::application_command::{ApplicationCommandInteraction, CommandDataOptionValue};
@@ -6,18 +5,21 @@ use serenity::prelude::Context;
 use serenity::prelude::SerenityError;
 use serenity::utils::Colour;
 
-use crate::engine::{parse_set};
-use crate::sentences::generate_sentences;
+use crate::client::{Client, SentenceRequest};
+use crate::formatters::{DiscordFormatter, SentenceFormatter};
+use crate::sentences::SelectionMode;
+use crate::share::import;
 use crate::shared::console_stamp;
 
-pub async fn run(interaction: &ApplicationCommandInteraction, ctx: &Context, db: Conn) -> Result<(), SerenityError> {
+pub async fn run(interaction: &ApplicationCommandInteraction, ctx: &Context, client: &Client) -> Result<(), SerenityError> {
 
-    let raw_set = match interaction.data.options.get(0).expect("").resolved.as_ref().expect("") {
+    let raw_set = match interaction.data.options.first().expect("").resolved.as_ref().expect("") {
         CommandDataOptionValue::String(value) => value,
         _ => return Ok(())
     };
-    let set = match parse_set(raw_set) {
-        Ok(set) => set,
+    // Links and share tokens work here too, not just sets typed out by hand
+    let shared = match import(raw_set) {
+        Ok(shared) => shared,
         Err(error) => {
             return interaction.create_interaction_response(&ctx.http, 
                 |r| r.interaction_response_data(|m|
@@ -31,7 +33,20 @@ pub async fn run(interaction: &ApplicationCommandInteraction, ctx: &Context, db:
         }
     };
 
-    match generate_sentences(vec![], vec![set], db, "", "").await {
+    let names = shared.name.into_iter().collect();
+    // The engine falls back to the guild's filter and packs by itself
+    let request = SentenceRequest {
+        guild: interaction.guild_id.map(|guild| guild.to_string()),
+        user: Some(interaction.user.id.to_string()),
+        // Discord's locales are already tags like `pt-BR`
+        locale: Some(interaction.locale.clone()),
+        // Someone trying a set out should see every form of it
+        mode: SelectionMode::Coverage,
+        ..Default::default()
+    };
+    let sentences = client.sentences(names, vec![shared.set], request).await
+        .map(|generated| DiscordFormatter::default().format(&generated));
+    match sentences {
         Ok(result) => interaction.create_interaction_response(&ctx.http, |r| r.interaction_response_data(
             |m|
             m.content(result)
@@ -0,0 +1,328 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use mysql_async::{Opts, OptsBuilder};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::engine::InferenceRules;
+use crate::http::HttpConfig;
+use crate::server::ServerConfig;
+use crate::storage::StoreConfig;
+use crate::storage::cache::CacheConfig;
+
+/// Environment variables starting with this override settings, with `__`
+/// between levels, like `PRONOUN_ENGINE_DATABASE__PASSWORD`
+pub const ENV_PREFIX: &str = "PRONOUN_ENGINE_";
+/// Where the config is read from when no path is given, if it's there
+pub const DEFAULT_PATH: &str = "config.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Uses in-memory storage whatever `storage` says, and logs every request
+    pub testing_mode: bool,
+    pub database: DBConfig,
+    /// Relative paths are in `$XDG_RUNTIME_DIR`, or the temporary directory
+    /// if that isn't set
+    pub engine_socket: String,
+    pub inference: InferenceRules,
+    pub storage: StoreConfig,
+    pub cache: CacheConfig,
+    pub server: ServerConfig,
+    /// Leaving this out means no HTTP API, only the socket
+    pub http: Option<HttpConfig>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            testing_mode: false,
+            database: DBConfig::default(),
+            engine_socket: "pronoun_engine.sock".to_owned(),
+            inference: InferenceRules::default(),
+            storage: StoreConfig::default(),
+            cache: CacheConfig::default(),
+            server: ServerConfig::default(),
+            http: None
+        }
+    }
+}
+
+/// Only needed for the MySQL backend
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DBConfig {
+    pub address: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub database: String,
+    pub salt: String
+}
+
+impl Default for DBConfig {
+    fn default() -> Self {
+        DBConfig {
+            address: "localhost".to_owned(),
+            port: 3306,
+            username: "".to_owned(),
+            password: "".to_owned(),
+            database: "".to_owned(),
+            salt: "".to_owned()
+        }
+    }
+}
+
+impl DBConfig {
+    /// Built up piece by piece rather than as a URL, so passwords can have
+    /// any characters in them
+    pub fn opts(&self) -> Opts {
+        let optional = |value: &str| (!value.is_empty()).then(|| value.to_owned());
+        OptsBuilder::default()
+            .ip_or_hostname(self.address.clone())
+            .tcp_port(self.port)
+            .user(optional(&self.username))
+            .pass(optional(&self.password))
+            .db_name(optional(&self.database))
+            .into()
+    }
+}
+
+// The file's values go over the defaults, keeping defaults for anything it
+// leaves out, even inside sections
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over
+    }
+}
+
+// Environment variables are all strings, so they're read as JSON unless the
+// setting was a string already. That way a password of `1234` stays a string
+fn override_with(config: &mut Value, path: &[String], raw: &str) {
+    let mut value = config;
+    for key in path {
+        if !value.is_object() {
+            *value = Value::Object(Map::new());
+        }
+        value = value.as_object_mut().unwrap().entry(key.clone()).or_insert(Value::Null);
+    }
+    *value = match value {
+        Value::String(_) => Value::String(raw.to_owned()),
+        _ => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_owned()))
+    };
+}
+
+fn read_file(path: &str) -> Result<Value, String> {
+    let contents = std::fs::read_to_string(path).map_err(|error| format!("Couldn't read {path}: {error}"))?;
+    let extension = Path::new(path).extension().and_then(|extension| extension.to_str()).unwrap_or("json");
+    match extension.to_lowercase().as_str() {
+        "toml" => toml::from_str(&contents).map_err(|error| format!("{path} isn't valid TOML: {error}")),
+        "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(|error| format!("{path} isn't valid YAML: {error}")),
+        _ => serde_json::from_str(&contents).map_err(|error| format!("{path} isn't valid JSON: {error}"))
+    }
+}
+
+impl Config {
+    /// Reads the config from `path`, or `$PRONOUN_ENGINE_CONFIG`, or
+    /// `config.json` if it exists, then applies any environment overrides.
+    /// JSON, TOML and YAML all work, going by the extension. Only the shape
+    /// is checked here, see `validate` for the rest
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let path = path.map(|path| path.to_owned())
+            .or_else(|| env::var(format!("{ENV_PREFIX}CONFIG")).ok())
+            .or_else(|| Path::new(DEFAULT_PATH).exists().then(|| DEFAULT_PATH.to_owned()));
+
+        let mut config = serde_json::to_value(Config::default()).map_err(|error| error.to_string())?;
+        if let Some(path) = &path {
+            merge(&mut config, read_file(path)?);
+        }
+        for (name, raw) in env::vars_os() {
+            // Names that aren't UTF-8 can't be ours
+            let Some(name) = name.to_str() else { continue };
+            let Some(setting) = name.strip_prefix(ENV_PREFIX) else { continue };
+            if setting == "CONFIG" {
+                continue;
+            }
+            let Some(raw) = raw.to_str() else {
+                return Err(format!("{name} isn't valid UTF-8"));
+            };
+            let path: Vec<String> = setting.to_lowercase().split("__").map(|key| key.to_owned()).collect();
+            if config.get(&path[0]).is_none() {
+                return Err(format!("{name} doesn't match any setting"));
+            }
+            override_with(&mut config, &path, raw);
+        }
+
+        // Errors say where the problem is, like `database.port`
+        let config: Config = serde_path_to_error::deserialize(config).map_err(|error| match error.path().to_string().as_str() {
+            "." => error.inner().to_string(),
+            path => format!("`{path}`: {}", error.inner())
+        })?;
+        Ok(config)
+    }
+
+    /// Checks the settings make sense together, listing every problem at once
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        if self.engine_socket.trim().is_empty() {
+            problems.push("`engine_socket` can't be empty".to_owned());
+        }
+        match &self.storage {
+            // Testing mode never touches the database
+            StoreConfig::Mysql {} if !self.testing_mode => {
+                for (field, value) in [("address", &self.database.address), ("username", &self.database.username), ("database", &self.database.database)] {
+                    if value.trim().is_empty() {
+                        problems.push(format!("`database.{field}` has to be set to use MySQL"));
+                    }
+                }
+            }
+            StoreConfig::Sqlite { path } | StoreConfig::File { path } if path.trim().is_empty() => {
+                problems.push("`storage.path` can't be empty".to_owned());
+            }
+            _ => ()
+        }
+        let patterns = self.inference.possessive2.iter().map(|rule| &rule.pattern)
+            .chain(self.inference.reflexive_stems.iter().map(|rule| &rule.pattern));
+        for pattern in patterns {
+            if let Err(error) = Regex::new(pattern) {
+                problems.push(format!("`inference` has a broken pattern \"{pattern}\": {error}"));
+            }
+        }
+        let server = [
+            ("max_connections", self.server.max_connections as u64),
+            ("max_in_flight", self.server.max_in_flight as u64),
+            ("max_pipelined", self.server.max_pipelined as u64),
+            ("idle_timeout_seconds", self.server.idle_timeout_seconds),
+            ("write_timeout_seconds", self.server.write_timeout_seconds)
+        ];
+        for (field, value) in server {
+            if value == 0 {
+                problems.push(format!("`server.{field}` has to be more than 0"));
+            }
+        }
+        if let Some(http) = &self.http {
+            if let Err(error) = http.address.parse::<SocketAddr>() {
+                problems.push(format!("`http.address` \"{}\" isn't an address and port: {error}", http.address));
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems.join("\n"))
+        }
+    }
+
+    pub fn socket_path(&self) -> PathBuf {
+        let socket = Path::new(&self.engine_socket);
+        if socket.is_absolute() {
+            return socket.to_owned();
+        }
+        match env::var("XDG_RUNTIME_DIR") {
+            Ok(directory) if !directory.is_empty() => Path::new(&directory).join(socket),
+            _ => env::temp_dir().join(socket)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Everything that touches the environment is in this one test, since
+    // tests run at the same time and `load` reads all of it
+    #[test]
+    fn files_and_environment() {
+        let path = env::temp_dir().join(format!("pronoun-engine-test-{}.toml", std::process::id()));
+        std::fs::write(&path, "engine_socket = \"from-file.sock\"\n[database]\nport = 3307\nusername = \"file\"\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        env::set_var("PRONOUN_ENGINE_DATABASE__PASSWORD", "1234");
+        env::set_var("PRONOUN_ENGINE_DATABASE__USERNAME", "env");
+        env::set_var("PRONOUN_ENGINE_SERVER__MAX_CONNECTIONS", "7");
+        let config = Config::load(Some(path));
+        env::remove_var("PRONOUN_ENGINE_DATABASE__PASSWORD");
+        env::remove_var("PRONOUN_ENGINE_DATABASE__USERNAME");
+        env::remove_var("PRONOUN_ENGINE_SERVER__MAX_CONNECTIONS");
+        let config = config.unwrap();
+        assert_eq!(config.engine_socket, "from-file.sock");
+        assert_eq!(config.database.port, 3307);
+        // Still a string even though it looks like a number
+        assert_eq!(config.database.password, "1234");
+        assert_eq!(config.database.username, "env");
+        assert_eq!(config.server.max_connections, 7);
+        assert_eq!(config.server.max_in_flight, ServerConfig::default().max_in_flight);
+
+        env::set_var("PRONOUN_ENGINE_NOPE", "1");
+        let unknown = Config::load(Some(path));
+        env::remove_var("PRONOUN_ENGINE_NOPE");
+        assert!(unknown.unwrap_err().contains("PRONOUN_ENGINE_NOPE"));
+
+        env::set_var("PRONOUN_ENGINE_SERVER__MAX_CONNECTIONS", "lots");
+        let wrong_type = Config::load(Some(path));
+        env::remove_var("PRONOUN_ENGINE_SERVER__MAX_CONNECTIONS");
+        assert!(wrong_type.unwrap_err().contains("server.max_connections"));
+
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            env::set_var(OsStr::from_bytes(b"PRONOUN_ENGINE_\xff"), "1");
+            let ignored = Config::load(Some(path));
+            env::remove_var(OsStr::from_bytes(b"PRONOUN_ENGINE_\xff"));
+            assert!(ignored.is_ok());
+            env::set_var("PRONOUN_ENGINE_DATABASE__PASSWORD", OsStr::from_bytes(b"\xff"));
+            let broken = Config::load(Some(path));
+            env::remove_var("PRONOUN_ENGINE_DATABASE__PASSWORD");
+            assert!(broken.unwrap_err().contains("isn't valid UTF-8"));
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unknown_fields_in_sections() {
+        for section in ["server", "cache", "http", "storage"] {
+            let mut config = serde_json::to_value(Config::default()).unwrap();
+            config[section] = serde_json::json!({ "address": "127.0.0.1:80", "backend": "memory", "typo": 1 });
+            assert!(serde_json::from_value::<Config>(config).is_err(), "{section}");
+        }
+        // Sections that are fine as they are, apart from the typo
+        for pointer in ["/database", "/inference", "/inference/possessive2/0", "/inference/reflexive_stems/0"] {
+            let mut config = serde_json::to_value(Config::default()).unwrap();
+            assert!(serde_json::from_value::<Config>(config.clone()).is_ok(), "{pointer}");
+            config.pointer_mut(pointer).unwrap()["typo"] = serde_json::json!(1);
+            assert!(serde_json::from_value::<Config>(config).is_err(), "{pointer}");
+        }
+    }
+
+    #[test]
+    fn validation_lists_every_problem() {
+        let mut config = Config { testing_mode: true, ..Config::default() };
+        assert_eq!(config.validate(), Ok(()));
+        config.server.max_pipelined = 0;
+        config.engine_socket = " ".to_owned();
+        let problems = config.validate().unwrap_err();
+        assert!(problems.contains("`server.max_pipelined`"), "{problems}");
+        assert!(problems.contains("`engine_socket`"), "{problems}");
+    }
+
+    #[test]
+    fn database_options_keep_passwords_as_they_are() {
+        let database = DBConfig { password: "p@ss:w/rd#".to_owned(), database: "pronouns".to_owned(), ..DBConfig::default() };
+        let opts = database.opts();
+        assert_eq!(opts.pass(), Some("p@ss:w/rd#"));
+        assert_eq!(opts.db_name(), Some("pronouns"));
+        assert_eq!(opts.user(), None);
+    }
+}
@@ -4,7 +4,7 @@ use rand::seq::SliceRandom;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PronounSet {
     subjective: String,
     objective: String,
@@ -55,117 +55,343 @@ static IT_IT: Lazy<PronounSet> = Lazy::new(|| {
     }
 });
 
-fn second_possessive(first: &str) -> String {
-    let last_letter = first.chars().last().unwrap();
-    if last_letter != 's' && last_letter != 'z' {
-        let mut possessive2: String = first.to_string();
-        possessive2.push_str("s");
-        return possessive2;
-    } else {
-        return first.to_string();
+/// The five forms every pronoun set has, in the order they're written
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Form {
+    Subjective,
+    Objective,
+    Possessive,
+    Possessive2,
+    Reflexive
+}
+
+impl Form {
+    pub const ALL: [Form; 5] = [Form::Subjective, Form::Objective, Form::Possessive, Form::Possessive2, Form::Reflexive];
+
+    /// The name used for this form in `[placeholder]`s and config files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Form::Subjective => "subjective",
+            Form::Objective => "objective",
+            Form::Possessive => "possessive",
+            Form::Possessive2 => "possessive2",
+            Form::Reflexive => "reflexive"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Form> {
+        Form::ALL.into_iter().find(|form| form.name() == name)
+    }
+
+    /// A human-readable name for showing the form to users
+    pub fn label(&self) -> &'static str {
+        match self {
+            Form::Subjective => "Subjective",
+            Form::Objective => "Objective",
+            Form::Possessive => "Possessive determiner",
+            Form::Possessive2 => "Possessive pronoun",
+            Form::Reflexive => "Reflexive"
+        }
+    }
+}
+
+impl PronounSet {
+    pub fn form(&self, form: Form) -> &str {
+        match form {
+            Form::Subjective => &self.subjective,
+            Form::Objective => &self.objective,
+            Form::Possessive => &self.possessive,
+            Form::Possessive2 => &self.possessive2,
+            Form::Reflexive => &self.reflexive
+        }
+    }
+
+    pub fn plural(&self) -> bool {
+        self.plural
+    }
+
+    /// The shortest way of writing this set that `parse_set` reads back the
+    /// same, like "she/her", "star/starself" or "xe/xem/xyr"
+    pub fn short(&self) -> String {
+        let forms: Vec<&str> = Form::ALL.iter().map(|form| self.form(*form)).collect();
+        let candidates = [
+            format!("{}/{}", forms[0], forms[1]),
+            format!("{}/{}", forms[0], forms[4]),
+            forms[..3].join("/"),
+            [forms[0], forms[1], forms[2], forms[4]].join("/"),
+            forms.join("/")
+        ];
+        // Plurality is only spelled out when it wouldn't be inferred anyway
+        for candidate in candidates {
+            for plural in ["", ":s", ":p"] {
+                let short = candidate.clone() + plural;
+                if parse_set(&short).as_ref() == Ok(self) {
+                    return short;
+                }
+            }
+        }
+        forms.join("/") + if self.plural { ":p" } else { ":s" }
+    }
+
+    /// Builds a nounself set like star/star/star's/star's/starself. Emoji work
+    /// the same way, giving 🌟/🌟/🌟's/🌟's/🌟self
+    pub fn nounself(noun: &str) -> PronounSet {
+        let possessive = noun.to_owned() + "'s";
+        PronounSet {
+            subjective: noun.to_owned(),
+            objective: noun.to_owned(),
+            possessive: possessive.clone(),
+            possessive2: possessive,
+            reflexive: noun.to_owned() + "self",
+            plural: false
+        }
+    }
+}
+
+/// Fills in the second possessive: the first rule whose pattern matches the
+/// possessive has its suffix appended to it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuffixRule {
+    pub pattern: String,
+    pub suffix: String
+}
+
+/// Picks which form the reflexive is built on: "xemself" uses the objective
+/// and "xyrself" uses the possessive
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StemRule {
+    /// Which given form `pattern` is checked against
+    pub form: Form,
+    pub pattern: String,
+    pub stem: Form
+}
+
+/// How missing forms get filled in when a custom set isn't written out in full
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InferenceRules {
+    pub possessive2: Vec<SuffixRule>,
+    pub reflexive_stems: Vec<StemRule>,
+    /// Used when none of the stem rules match
+    pub default_reflexive_stem: Form,
+    pub reflexive_suffix: String,
+    /// Subjectives that make a set plural when `:s`/`:p` isn't given
+    pub plural_subjectives: Vec<String>,
+    pub default_plural: bool
+}
+
+impl Default for InferenceRules {
+    fn default() -> Self {
+        InferenceRules {
+            possessive2: vec![
+                // "his", "its", "zirs" stay as they are
+                SuffixRule { pattern: "[sz]$".to_owned(), suffix: "".to_owned() },
+                SuffixRule { pattern: "".to_owned(), suffix: "s".to_owned() }
+            ],
+            reflexive_stems: vec![
+                // xem -> xemself, them -> themself
+                StemRule { form: Form::Objective, pattern: "m$".to_owned(), stem: Form::Objective },
+                // xyr -> xyrself, faer -> faerself
+                StemRule { form: Form::Possessive, pattern: "r$".to_owned(), stem: Form::Possessive }
+            ],
+            default_reflexive_stem: Form::Objective,
+            reflexive_suffix: "self".to_owned(),
+            plural_subjectives: vec!["they".to_owned()],
+            default_plural: false
+        }
+    }
+}
+
+/// A form that wasn't given and had to be worked out
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InferredForm {
+    pub form: Form,
+    pub reason: String
+}
+
+/// The result of parsing a set, along with anything that wasn't given
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InferredSet {
+    pub set: PronounSet,
+    pub inferred: Vec<InferredForm>,
+    /// Only set if plurality wasn't given with `:s` or `:p`
+    pub plural_reason: Option<String>
+}
+
+fn rule_matches(pattern: &str, value: &str) -> bool {
+    // Broken patterns never match rather than failing every parse
+    match Regex::new(pattern) {
+        Ok(regex) => regex.is_match(value),
+        Err(_) => false
+    }
+}
+
+impl InferenceRules {
+    fn second_possessive(&self, first: &str) -> (String, String) {
+        for rule in &self.possessive2 {
+            if rule_matches(&rule.pattern, first) {
+                let reason = if rule.suffix.is_empty() {
+                    format!("`{}` matches `{}` so it was used as-is", first, rule.pattern)
+                } else {
+                    format!("`{}` was added to `{}`", rule.suffix, first)
+                };
+                return (first.to_owned() + &rule.suffix, reason);
+            }
+        }
+        (first.to_owned(), format!("No rule matched `{first}` so it was used as-is"))
+    }
+
+    fn reflexive_stem(&self, subjective: &str, objective: &str, possessive: &str) -> (Form, String) {
+        for rule in &self.reflexive_stems {
+            let value = match rule.form {
+                Form::Subjective => subjective,
+                Form::Objective => objective,
+                Form::Possessive => possessive,
+                // These are never given when the reflexive is missing
+                Form::Possessive2 | Form::Reflexive => continue
+            };
+            if rule_matches(&rule.pattern, value) {
+                return (rule.stem, format!("The {} `{}` matches `{}`", rule.form.name(), value, rule.pattern));
+            }
+        }
+        (self.default_reflexive_stem, "No stem rule matched".to_owned())
+    }
+
+    fn plural(&self, subjective: &str) -> (bool, String) {
+        if self.plural_subjectives.iter().any(|plural| plural.eq(subjective)) {
+            (true, format!("`{subjective}` is a plural subjective"))
+        } else if self.default_plural {
+            (true, "Sets are plural by default".to_owned())
+        } else {
+            (false, "Sets are singular by default".to_owned())
+        }
     }
 }
 
 const GENERIC_FORMAT_ERROR: &str = "Pronouns must be in the form `subjective/objective/possessive/posessive/reflexive`. See `/help` for more info.";
+const PLURAL_FORMAT_ERROR: &str = "Plurality must be given as `:s` (singular) or `:p` (plural).";
 
-pub fn parse_set(raw: &str) -> Result<PronounSet, &str> {
-    let colon_index = raw.find(":");
-    let mut plural = false;
+pub fn parse_set(raw: &str) -> Result<PronounSet, &'static str> {
+    parse_set_with(raw, &InferenceRules::default()).map(|inferred| inferred.set)
+}
+
+pub fn parse_set_with(raw: &str, rules: &InferenceRules) -> Result<InferredSet, &'static str> {
+    let mut plural = None;
     let mut colon_removed = raw.trim().to_lowercase();
-    match colon_index {
-        None => (),
-        Some(index) => {
-            let plural_str = &raw[index + 1..].trim().to_lowercase();
-            colon_removed = raw[..index].to_lowercase();
-            if plural_str.eq("p") || plural_str.eq("pl") || plural_str.eq("plural") {
-                plural = true;
-            } else if !(plural_str.eq("s") || plural_str.eq("singular")) {
-                // This means some invalid value was specified
-                // Throw an error
-            }
+    if let Some(index) = raw.find(':') {
+        let plural_str = &raw[index + 1..].trim().to_lowercase();
+        colon_removed = raw[..index].trim().to_lowercase();
+        match plural_str.as_str() {
+            "p" | "pl" | "plural" => plural = Some(true),
+            "s" | "singular" => plural = Some(false),
+            _ => return Err(PLURAL_FORMAT_ERROR)
         }
     }
-    let splitter = colon_removed.split("/");
     let mut split: Vec<String> = Vec::new();
-    for term in splitter {
+    for term in colon_removed.split('/') {
         if term.is_empty() {
             return Err("There needs to be a pronoun between each slash.");
         }
         split.push(term.to_string());
     }
 
-    match split.len() {
-        1 => {
-            let first = split[0].clone();
-            if first.eq("he") {
-                return Ok(HE_HIM.clone());
-            } else if first.eq("she") {
-                return Ok(SHE_HER.clone());
-            } else if first.eq("they") {
-                return Ok(THEY_THEM.clone());
-            } else if first.eq("it") {
-                return Ok(IT_IT.clone());
-            } else {
-                Err(GENERIC_FORMAT_ERROR)
-            }
-        }
-        2 => {
-            let first = split[0].clone();
-            let second = split[1].clone();
-            if first.eq("he") && second.eq("him") {
-                return Ok(HE_HIM.clone());
-            } else if first.eq("she") && second.eq("her") {
-                return Ok(SHE_HER.clone());
-            } else if first.eq("they") && second.eq("them") {
-                return Ok(THEY_THEM.clone());
-            } else if first.eq("it") && (second.eq("it") || second.eq("its")) {
-                return Ok(IT_IT.clone());
-            } else {
-                Err(GENERIC_FORMAT_ERROR)
+    let known = match split.len() {
+        1 => match split[0].as_str() {
+            "he" => Some(HE_HIM.clone()),
+            "she" => Some(SHE_HER.clone()),
+            "they" => Some(THEY_THEM.clone()),
+            "it" => Some(IT_IT.clone()),
+            _ => return Err(GENERIC_FORMAT_ERROR)
+        },
+        2 => match (split[0].as_str(), split[1].as_str()) {
+            ("he", "him") | ("he", "himself") => Some(HE_HIM.clone()),
+            ("she", "her") | ("she", "herself") => Some(SHE_HER.clone()),
+            ("they", "them") | ("they", "themself") | ("they", "themselves") => Some(THEY_THEM.clone()),
+            ("it", "it") | ("it", "its") | ("it", "itself") => Some(IT_IT.clone()),
+            // Nounself shorthand, star/starself. The known sets above would
+            // match this too, so they have to come first
+            (noun, reflexive) if reflexive.strip_suffix("self") == Some(noun) => {
+                let mut set = PronounSet::nounself(noun);
+                let mut inferred = vec![];
+                for form in [Form::Objective, Form::Possessive, Form::Possessive2] {
+                    inferred.push(InferredForm { form, reason: format!("`{noun}` is a nounself set") });
+                }
+                set.plural = plural.unwrap_or(false);
+                return Ok(InferredSet {
+                    set,
+                    inferred,
+                    plural_reason: plural.map_or(Some("Nounself sets are singular by default".to_owned()), |_| None)
+                });
             }
+            _ => return Err(GENERIC_FORMAT_ERROR)
+        },
+        3..=5 => None,
+        _ => return Err(GENERIC_FORMAT_ERROR)
+    };
+    if let Some(mut set) = known {
+        // `they:s` is singular they
+        if let Some(plural) = plural {
+            set.plural = plural;
         }
+        return Ok(InferredSet { set, inferred: vec![], plural_reason: None });
+    }
+
+    let mut inferred = Vec::new();
+    let subjective = split[0].clone();
+    let objective = split[1].clone();
+    let possessive = split[2].clone();
+
+    let possessive2 = if split.len() == 5 {
+        split[3].clone()
+    } else {
+        let (possessive2, reason) = rules.second_possessive(&possessive);
+        inferred.push(InferredForm { form: Form::Possessive2, reason });
+        possessive2
+    };
+
+    let reflexive = match split.len() {
         3 => {
-            let subjective = split[0].clone();
-            let mut reflexive = subjective.clone();
-            reflexive.push_str("self");
-            let possessive = split[2].clone();
-            let possessive2 = second_possessive(&possessive);
-            Ok(PronounSet {
-                subjective: subjective,
-                objective: split[1].clone(),
-                possessive: possessive,
-                possessive2: possessive2,
-                reflexive: reflexive.to_string(),
-                plural: true
-            })
-        }
-        4 => {
-            let subjective = split[0].clone();
-            let possessive = split[2].clone();
-            let possessive2 = second_possessive(&possessive);
-            Ok(PronounSet {
-                subjective: subjective,
-                objective: split[1].clone(),
-                possessive: possessive,
-                possessive2,
-                reflexive: split[3].clone(),
-                plural
-            })
-        }
-        5 => {
-            Ok(PronounSet {
-                subjective: split[0].clone(),
-                objective: split[1].clone(),
-                possessive: split[2].clone(),
-                possessive2: split[3].clone(),
-                reflexive: split[4].clone(),
-                plural
-            })
+            let (stem, reason) = rules.reflexive_stem(&subjective, &objective, &possessive);
+            let stem_value = match stem {
+                Form::Subjective => &subjective,
+                Form::Objective => &objective,
+                Form::Possessive => &possessive,
+                Form::Possessive2 => &possessive2,
+                // Can't build a reflexive from itself
+                Form::Reflexive => &objective
+            };
+            inferred.push(InferredForm {
+                form: Form::Reflexive,
+                reason: format!("{reason} so it was built from the {}", stem.name())
+            });
+            stem_value.clone() + &rules.reflexive_suffix
         }
-        _ => {
-            Err(GENERIC_FORMAT_ERROR)
+        _ => split[split.len() - 1].clone()
+    };
+
+    let (plural, plural_reason) = match plural {
+        Some(plural) => (plural, None),
+        None => {
+            let (plural, reason) = rules.plural(&subjective);
+            (plural, Some(reason))
         }
-    }
+    };
+
+    Ok(InferredSet {
+        set: PronounSet {
+            subjective,
+            objective,
+            possessive,
+            possessive2,
+            reflexive,
+            plural
+        },
+        inferred,
+        plural_reason
+    })
 }
 
 //const piece = new RegExp("[^\u200B]\[(.*?)\]");
@@ -176,152 +402,284 @@ pub fn parse_set(raw: &str) -> Result<PronounSet, &str> {
 const PIECE: &str = r"(?:\{(.*?)\|(.*?)}( ?))?\[([^\u200B].*?)\](?:( ?\S*? ?)\{(.*?)\|(.*?)})?";
 // Returns: [whole, singular, plural, gap, pronoun, gap, singular, plural] because even I won't be able to understand this in two days
 
-pub fn genderify_text(text: &str, names: Vec<String>, sets: Vec<PronounSet>) -> String {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(PIECE).unwrap();
+// Capitalises the first letter and lowercases the rest. Anything uncased at
+// the start (emoji, digits, punctuation) is left alone so 🌟self stays as-is
+fn capitalise_first(text: &str) -> String {
+    match text.char_indices().next() {
+        Some((_, char)) if !char.is_alphabetic() => text.to_owned(),
+        Some((_, char)) => char.to_uppercase().to_string() + &text[char.len_utf8()..].to_lowercase(),
+        None => "".to_owned() // Not possible to have a blank string here
     }
+}
 
-    let mut final_text = text.to_owned();
-    
-    // While there are matches, loop
+lazy_static! {
+    static ref RE: Regex = Regex::new(PIECE).unwrap();
+}
+
+/// What's inside each `[placeholder]` in a template, without capitalisation
+/// markers, and how many `{singular|plural}` pieces were attached to them
+pub fn template_placeholders(text: &str) -> (Vec<&str>, usize) {
+    let mut placeholders = Vec::new();
+    let mut agreements = 0;
     for match_result in RE.captures_iter(text) {
-        let central_match = match_result.get(4);
-        let mut central = "";
-        let mut capitals: u8 = 0;
-        let mut form_before = "";
-        let mut form_after = "";
-
-        match central_match {
-            Some(central_match) => {
-                central = central_match.as_str();
-            }
-            None => () // Invalid sentence
-        };
-
-        match central.chars().nth(0) {
-            Some(char) => {
-                if char == '^' {
-                    capitals = 1; // First letter
-                    central = &central[1..];
+        if let Some(central) = match_result.get(4) {
+            placeholders.push(central.as_str().trim_matches('^'));
+        }
+        agreements += [1, 6].iter().filter(|group| match_result.get(**group).is_some()).count();
+    }
+    (placeholders, agreements)
+}
+
+/// A template with its placeholders already found, so it can be filled in
+/// again and again without being matched each time
+#[derive(Clone, Debug)]
+pub struct Template {
+    text: String,
+    // Where each of `PIECE`'s groups matched, for every match
+    matches: Vec<[Option<(usize, usize)>; 8]>,
+    /// What's inside each `[placeholder]`, see `template_placeholders`
+    pub placeholders: Vec<String>,
+    /// Every form it uses, in the order they first appear
+    pub forms: Vec<Form>
+}
+
+impl Template {
+    pub fn compile(text: &str) -> Template {
+        let matches: Vec<[Option<(usize, usize)>; 8]> = RE.captures_iter(text)
+            .map(|captures| std::array::from_fn(|group| captures.get(group).map(|found| (found.start(), found.end()))))
+            .collect();
+        let placeholders: Vec<String> = matches.iter()
+            .filter_map(|groups| groups[4].map(|(start, end)| text[start..end].trim_matches('^').to_owned()))
+            .collect();
+        let mut forms = Vec::new();
+        for placeholder in &placeholders {
+            if let Some(form) = Form::from_name(placeholder) {
+                if !forms.contains(&form) {
+                    forms.push(form);
                 }
             }
-            None => () // Invalid sentence
         }
-        match central.chars().nth_back(0) {
-            Some(char) => {
-                if char == '^' {
-                    capitals = 2; // All
-                    central = &central[..central.len() - 1];
+        Template { text: text.to_owned(), matches, placeholders, forms }
+    }
+
+    // Every optional capture is matched on the same way, even where `if let` would do
+    #[allow(clippy::single_match, clippy::collapsible_match)]
+    pub fn genderify(&self, names: Vec<String>, sets: Vec<PronounSet>) -> String {
+
+        let mut final_text = self.text.clone();
+    
+        // While there are matches, loop
+        for captures in &self.matches {
+            let match_result = |group: usize| captures[group].map(|(start, end)| &self.text[start..end]);
+            let central_match = match_result(4);
+            let mut central = "";
+            let mut capitals: u8 = 0;
+            let mut form_before = "";
+            let mut form_after = "";
+
+            match central_match {
+                Some(central_match) => {
+                    central = central_match;
                 }
+                None => () // Invalid sentence
+            };
+
+            match central.chars().next() {
+                Some(char) => {
+                    if char == '^' {
+                        capitals = 1; // First letter
+                        central = &central[1..];
+                    }
+                }
+                None => () // Invalid sentence
             }
-            None => () // Error
-        }
-        
-        match central {
-            "name" => {
-                let name = names.choose(&mut rand::thread_rng());
-
-                match name {
-                    Some(name) => {
-                        central = name;
-                        if capitals == 0 {
-                            capitals = 1;
-                        }
+            match central.chars().nth_back(0) {
+                Some(char) => {
+                    if char == '^' {
+                        capitals = 2; // All
+                        central = &central[..central.len() - 1];
                     }
-                    None => () // This is covered in a previous function
                 }
+                None => () // Error
             }
-            "subjective" | "objective" | "possessive" | "possessive2" | "reflexive" => {
-                // Pick a random set
-                let set = sets.choose(&mut rand::thread_rng());
-
-                match set {
-                    Some(set) => {
-                        // Set the central
-                        match central {
-                            // These are all &Strings coerced to &strs
-                            "subjective" => central = &set.subjective,
-                            "objective" => central = &set.objective,
-                            "possessive" => central = &set.possessive,
-                            "possessive2" => central = &set.possessive2,
-                            "reflexive" => central = &set.reflexive,
-                            _ => ()
+        
+            match central {
+                "name" => {
+                    let name = names.choose(&mut rand::thread_rng());
+
+                    match name {
+                        Some(name) => {
+                            central = name;
+                            if capitals == 0 {
+                                capitals = 1;
+                            }
                         }
+                        None => () // This is covered in a previous function
+                    }
+                }
+                "subjective" | "objective" | "possessive" | "possessive2" | "reflexive" => {
+                    // Pick a random set
+                    let set = sets.choose(&mut rand::thread_rng());
+
+                    match set {
+                        Some(set) => {
+                            // Set the central
+                            match central {
+                                // These are all &Strings coerced to &strs
+                                "subjective" => central = &set.subjective,
+                                "objective" => central = &set.objective,
+                                "possessive" => central = &set.possessive,
+                                "possessive2" => central = &set.possessive2,
+                                "reflexive" => central = &set.reflexive,
+                                _ => ()
+                            }
+
+                            if set.plural {
+                                // Plural set
+
+                                match match_result(2) {
+                                    Some(before) => {
+                                        form_before = before;
+                                    }
+                                    None => ()
+                                };
+                                match match_result(7) {
+                                    Some(after) => {
+                                        form_after = after;
+                                    }
+                                    None => ()
+                                };
+                            } else {
+                                // Singular set
 
-                        if set.plural {
-                            // Plural set
-
-                            match match_result.get(2) {
-                                Some(before) => {
-                                    form_before = before.as_str();
-                                }
-                                None => ()
-                            };
-                            match match_result.get(7) {
-                                Some(after) => {
-                                    form_after = after.as_str();
-                                }
-                                None => ()
-                            };
-                        } else {
-                            // Singular set
-
-                            match match_result.get(1) {
-                                Some(before) => {
-                                    form_before = before.as_str();
-                                }
-                                None => ()
-                            };
-                            match match_result.get(6) {
-                                Some(after) => {
-                                    form_after = after.as_str();
-                                }
-                                None => ()
-                            };
+                                match match_result(1) {
+                                    Some(before) => {
+                                        form_before = before;
+                                    }
+                                    None => ()
+                                };
+                                match match_result(6) {
+                                    Some(after) => {
+                                        form_after = after;
+                                    }
+                                    None => ()
+                                };
+                            }
                         }
+                        None => () // This is covered in a previous function
                     }
-                    None => () // This is covered in a previous function
                 }
+                _ => ()
             }
-            _ => ()
-        }
 
-        // Apply capitalisation
-        let applied_central: String = match capitals {
-            0 => central.to_owned(),
-            1 => {
-                // Capitalise first letter
-                match central.chars().nth(0) {
-                    Some(char) => char.to_uppercase().to_string() + &central[1..].to_lowercase(),
-                    None => "".to_owned() // Not possible to have a blank string here
+            // Apply capitalisation
+            let applied_central: String = match capitals {
+                0 => central.to_owned(),
+                1 => capitalise_first(central),
+                2 => central.to_uppercase(),
+                _ => "".to_owned() // No other possibilities
+            };
+
+            let mut parsed: String = "".to_owned();
+            match match_result(1) {
+                Some(_) => {
+                    parsed += &(form_before.to_owned() + match_result(3).unwrap_or(""))
                 }
-            },
-            2 => central.to_uppercase(),
-            _ => "".to_owned() // No other possibilities
-        };
-
-        let mut parsed: String = "".to_owned();
-        match match_result.get(1) {
-            Some(_) => {
-                parsed += &(form_before.to_owned() + match match_result.get(3) {
-                    Some(val) => val.as_str(),
-                    None => ""
-                })
+                None => ()
             }
-            None => ()
-        }
-        parsed += &applied_central.replace("[", "[ESCAPE CODE");
-        match match_result.get(6) {
-            Some(_) => {
-                parsed += &(match match_result.get(5) {
-                    Some(val) => val.as_str(),
-                    None => ""
-                }.to_owned() + form_after)
+            parsed += &applied_central.replace("[", "[ESCAPE CODE");
+            match match_result(6) {
+                Some(_) => {
+                    parsed += &(match_result(5).unwrap_or("").to_owned() + form_after)
+                }
+                None => ()
             }
-            None => ()
+
+            final_text = final_text.replace(match_result(0).expect("This should be impossible"), parsed.as_str());
         }
+        final_text
+    }
+}
+
+pub fn genderify_text(text: &str, names: Vec<String>, sets: Vec<PronounSet>) -> String {
+    Template::compile(text).genderify(names, sets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_sets_written_with_their_reflexive() {
+        for (raw, known) in [("he/himself", &*HE_HIM), ("she/herself", &*SHE_HER), ("they/themself", &*THEY_THEM), ("it/itself", &*IT_IT)] {
+            let parsed = parse_set_with(raw, &InferenceRules::default()).unwrap();
+            assert_eq!(&parsed.set, known, "{raw}");
+            assert!(parsed.inferred.is_empty(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn partial_sets_are_filled_in() {
+        let parsed = parse_set_with("xe/xem/xyr", &InferenceRules::default()).unwrap();
+        assert_eq!(parsed.set.form(Form::Possessive2), "xyrs");
+        assert_eq!(parsed.set.form(Form::Reflexive), "xemself");
+        assert!(!parsed.set.plural());
+        let forms: Vec<Form> = parsed.inferred.iter().map(|inferred| inferred.form).collect();
+        assert_eq!(forms, vec![Form::Possessive2, Form::Reflexive]);
+        assert!(parsed.plural_reason.is_some());
+
+        let parsed = parse_set_with("ze/zir/zirs", &InferenceRules::default()).unwrap();
+        assert_eq!(parsed.set.form(Form::Possessive2), "zirs");
+    }
+
+    #[test]
+    fn rules_can_be_changed() {
+        let rules = InferenceRules { plural_subjectives: vec!["ey".to_owned()], reflexive_suffix: "selves".to_owned(), ..Default::default() };
+        let parsed = parse_set_with("ey/em/eir", &rules).unwrap();
+        assert!(parsed.set.plural());
+        assert_eq!(parsed.set.form(Form::Reflexive), "emselves");
+    }
+
+    #[test]
+    fn plurality_can_be_given() {
+        let rules = InferenceRules::default();
+        assert!(parse_set_with("xe/xem/xyr:p", &rules).unwrap().set.plural());
+        let parsed = parse_set_with("xe/xem/xyr/xyrs/xemself:s", &rules).unwrap();
+        assert!(!parsed.set.plural());
+        assert!(parsed.inferred.is_empty());
+        assert!(parsed.plural_reason.is_none());
+        assert_eq!(parse_set_with("xe/xem/xyr:q", &rules).unwrap_err(), PLURAL_FORMAT_ERROR);
+
+        // The built-in sets can be given one too
+        assert!(!parse_set_with("they:s", &rules).unwrap().set.plural());
+        for raw in ["she/her:p", "she:p", "it/its:p"] {
+            assert!(parse_set_with(raw, &rules).unwrap().set.plural(), "{raw}");
+        }
+        assert!(!parse_set_with("she/her", &rules).unwrap().set.plural());
+    }
+
+    #[test]
+    fn malformed_sets() {
+        let rules = InferenceRules::default();
+        for raw in ["", "xe", "xe/xem", "a/b/c/d/e/f", "xe//xyr"] {
+            assert!(parse_set_with(raw, &rules).is_err(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn compiled_templates() {
+        let template = Template::compile("{Is|Are} [^subjective] here? [name] asked [^reflexive^].");
+        assert_eq!(template.placeholders, vec!["subjective", "name", "reflexive"]);
+        assert_eq!(template.forms, vec![Form::Subjective, Form::Reflexive]);
+        let names = vec!["Ada".to_owned()];
+        assert_eq!(template.genderify(names.clone(), vec![SHE_HER.clone()]), "Is She here? Ada asked HERSELF.");
+        assert_eq!(template.genderify(names, vec![THEY_THEM.clone()]), "Are They here? Ada asked THEMSELF.");
+    }
 
-        final_text = final_text.replace(match_result.get(0).expect("This should be impossible").as_str(), parsed.as_str());
+    #[test]
+    fn nounself_shorthand() {
+        let parsed = parse_set_with("star/starself", &InferenceRules::default()).unwrap();
+        assert_eq!(parsed.set, PronounSet::nounself("star"));
+        assert_eq!(parsed.inferred.len(), 3);
     }
-    final_text
 }
\ No newline at end of file
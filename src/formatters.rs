@@ -0,0 +1,62 @@
+use crate::sentences::GeneratedSentences;
+
+/// Turns generated sentences into text for wherever they're being shown.
+/// Headings can use `{n}` for the sentence number
+pub trait SentenceFormatter {
+    fn format(&self, generated: &GeneratedSentences) -> String;
+}
+
+const DEFAULT_HEADING: &str = "Sentence {n}";
+
+fn format_with(generated: &GeneratedSentences, heading: impl Fn(usize) -> String) -> String {
+    let mut text = generated.intro.clone();
+    for (i, sentence) in generated.sentences.iter().enumerate() {
+        if !text.is_empty() {
+            text += "\n\n";
+        }
+        text += &heading(i + 1);
+        text += "\n";
+        text += sentence;
+    }
+    if !generated.outro.is_empty() {
+        if !text.is_empty() {
+            text += "\n\n";
+        }
+        text += &generated.outro;
+    }
+    text
+}
+
+/// No markup at all, for terminals and anything that isn't Markdown
+pub struct PlainFormatter {
+    pub heading: String
+}
+
+impl Default for PlainFormatter {
+    fn default() -> Self {
+        PlainFormatter { heading: DEFAULT_HEADING.to_owned() }
+    }
+}
+
+impl SentenceFormatter for PlainFormatter {
+    fn format(&self, generated: &GeneratedSentences) -> String {
+        format_with(generated, |n| self.heading.replace("{n}", &n.to_string()))
+    }
+}
+
+/// Bold headings, the way they've always looked on Discord
+pub struct DiscordFormatter {
+    pub heading: String
+}
+
+impl Default for DiscordFormatter {
+    fn default() -> Self {
+        DiscordFormatter { heading: DEFAULT_HEADING.to_owned() }
+    }
+}
+
+impl SentenceFormatter for DiscordFormatter {
+    fn format(&self, generated: &GeneratedSentences) -> String {
+        format_with(generated, |n| format!("**{}**", self.heading.replace("{n}", &n.to_string())))
+    }
+}
@@ -0,0 +1,104 @@
+use serde::Serialize;
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+// Requests and replies on the socket are newline-delimited JSON, one value
+// per line. Compact JSON never has a raw newline in it so this is safe
+
+/// The longest line that will be read, anything longer is skipped
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Frame {
+    Message(Vec<u8>),
+    /// The line was longer than the limit and has been skipped over
+    TooLong
+}
+
+fn is_blank(frame: &[u8]) -> bool {
+    frame.iter().all(|byte| byte.is_ascii_whitespace())
+}
+
+/// Reads up to the next newline, or to the end if the other side stops
+/// without one. `None` means there's nothing left. Blank lines are skipped
+pub async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R, limit: usize) -> io::Result<Option<Frame>> {
+    let mut frame = Vec::new();
+    let mut too_long = false;
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(match too_long {
+                true => Some(Frame::TooLong),
+                false if is_blank(&frame) => None,
+                false => Some(Frame::Message(frame))
+            });
+        }
+
+        let (line, complete) = match available.iter().position(|byte| *byte == b'\n') {
+            Some(end) => (&available[..end], true),
+            None => (available, false)
+        };
+        // Keep reading past the limit so the next frame starts in the right place
+        if !too_long && frame.len() + line.len() > limit {
+            too_long = true;
+            frame = Vec::new();
+        }
+        if !too_long {
+            frame.extend_from_slice(line);
+        }
+        let used = line.len() + complete as usize;
+        reader.consume(used);
+
+        if complete {
+            if too_long {
+                return Ok(Some(Frame::TooLong));
+            }
+            if is_blank(&frame) {
+                frame.clear();
+                continue;
+            }
+            return Ok(Some(Frame::Message(frame)));
+        }
+    }
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(writer: &mut W, value: &T) -> io::Result<()> {
+    let mut data = serde_json::to_vec(value).map_err(io::Error::from)?;
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::BufReader;
+
+    use super::*;
+
+    // A small buffer so frames arrive in pieces, like they would off a socket
+    async fn read_all(data: &[u8], limit: usize) -> Vec<Option<Vec<u8>>> {
+        let mut reader = BufReader::with_capacity(3, data);
+        let mut frames = Vec::new();
+        while let Some(frame) = read_frame(&mut reader, limit).await.unwrap() {
+            frames.push(match frame {
+                Frame::Message(data) => Some(data),
+                Frame::TooLong => None
+            });
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn lines_are_frames() {
+        let frames = read_all(b"{\"a\":1}\n\n  \n{\"b\":2}\n{\"c\":3}", 64).await;
+        assert_eq!(frames, vec![Some(b"{\"a\":1}".to_vec()), Some(b"{\"b\":2}".to_vec()), Some(b"{\"c\":3}".to_vec())]);
+        assert!(read_all(b"\n \n", 64).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn long_lines_are_skipped() {
+        let frames = read_all(b"12345\n123456\n1234", 5).await;
+        assert_eq!(frames, vec![Some(b"12345".to_vec()), None, Some(b"1234".to_vec())]);
+        // Even when the connection ends part way through one
+        assert_eq!(read_all(b"12\n123456789", 5).await, vec![Some(b"12".to_vec()), None]);
+    }
+}
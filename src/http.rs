@@ -0,0 +1,316 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use hyper::body::HttpBody;
+use hyper::header::{ACCEPT, ALLOW, CONTENT_LENGTH, CONTENT_TYPE, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request as HttpRequest, Response as HttpResponse, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::formatters::{DiscordFormatter, PlainFormatter, SentenceFormatter};
+use crate::framing::MAX_FRAME_SIZE;
+use crate::protocol::{DispatchError, Envelope, Request, Response};
+use crate::server::Server;
+use crate::shared::console_stamp as cs;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Like `127.0.0.1:8080`, keep it local unless something's in front of it
+    pub address: String
+}
+
+const JSON: &str = "application/json";
+const PLAIN: &str = "text/plain; charset=utf-8";
+const MARKDOWN: &str = "text/markdown; charset=utf-8";
+const SVG: &str = "image/svg+xml";
+
+/// Each endpoint is one socket command, the body is that command's fields
+struct Endpoint {
+    path: &'static str,
+    command: &'static str,
+    /// What it can reply with, the first is used when anything goes
+    formats: &'static [&'static str]
+}
+
+const ENDPOINTS: [Endpoint; 7] = [
+    Endpoint { path: "/hello", command: "hello", formats: &[JSON] },
+    Endpoint { path: "/genderify", command: "genderify", formats: &[JSON, PLAIN] },
+    Endpoint { path: "/genderify/batch", command: "genderify_batch", formats: &[JSON] },
+    Endpoint { path: "/sentences", command: "sentences", formats: &[JSON, PLAIN, MARKDOWN] },
+    Endpoint { path: "/parse", command: "parse", formats: &[JSON, PLAIN] },
+    Endpoint { path: "/card", command: "card_svg", formats: &[JSON, SVG] },
+    Endpoint { path: "/card/details", command: "card", formats: &[JSON, PLAIN, MARKDOWN] }
+];
+
+fn media_type(value: &str) -> &str {
+    value.split(';').next().unwrap_or("").trim()
+}
+
+/// Picks the best format the client accepts going by `q` values, ties go to
+/// whichever the endpoint lists first. `None` means nothing they'd take
+fn negotiate(accept: Option<&str>, formats: &'static [&'static str]) -> Option<&'static str> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return Some(formats[0])
+    };
+    let ranges: Vec<(&str, f32)> = accept.split(',').map(|range| {
+        let quality = range.split(';').skip(1)
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .find_map(|quality| quality.trim().parse().ok())
+            .unwrap_or(1.0);
+        (media_type(range), quality)
+    }).collect();
+
+    let mut best: Option<(&'static str, f32)> = None;
+    for format in formats {
+        let wanted = media_type(format);
+        let (kind, _) = wanted.split_once('/').unwrap_or((wanted, ""));
+        // The most specific range that matches is the one that counts
+        let quality = ranges.iter()
+            .filter_map(|(range, quality)| match *range {
+                range if range.eq_ignore_ascii_case(wanted) => Some((2, *quality)),
+                range if range.strip_suffix("/*").is_some_and(|range| range.eq_ignore_ascii_case(kind)) => Some((1, *quality)),
+                "*/*" => Some((0, *quality)),
+                _ => None
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality);
+        if let Some(quality) = quality {
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((format, quality));
+            }
+        }
+    }
+    best.map(|(format, _)| format)
+}
+
+fn reply(status: StatusCode, content_type: &str, body: impl Into<Body>) -> HttpResponse<Body> {
+    let mut response = HttpResponse::new(body.into());
+    *response.status_mut() = status;
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap_or(HeaderValue::from_static(JSON)));
+    response
+}
+
+fn reply_envelope(status: StatusCode, envelope: &Envelope) -> HttpResponse<Body> {
+    match serde_json::to_vec(envelope) {
+        Ok(body) => reply(status, JSON, body),
+        Err(error) => reply(StatusCode::INTERNAL_SERVER_ERROR, PLAIN, error.to_string())
+    }
+}
+
+/// Errors go back in whichever format was asked for, as JSON otherwise
+fn reply_error(status: StatusCode, format: &str, error: String, started: Instant) -> HttpResponse<Body> {
+    match format {
+        JSON => reply_envelope(status, &Envelope::new(None, Err(error), started)),
+        _ => reply(status, PLAIN, error)
+    }
+}
+
+// Reads the body, giving up once it's past the limit
+async fn read_body(body: &mut Body) -> Result<Vec<u8>, StatusCode> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if data.len() + chunk.len() > MAX_FRAME_SIZE {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Turns a body into the socket request it stands for, by adding the
+/// command's name
+fn to_request(data: &[u8], command: &str) -> Result<Request, String> {
+    let mut value: serde_json::Value = match data.is_empty() {
+        true => serde_json::json!({}),
+        false => serde_json::from_slice(data).map_err(|error| format!("Invalid request: {error}"))?
+    };
+    match value.as_object_mut() {
+        Some(fields) => fields.insert("name".to_owned(), serde_json::Value::String(command.to_owned())),
+        None => return Err("Invalid request: the body should be a JSON object".to_owned())
+    };
+    serde_json::from_value(value).map_err(|error| format!("Invalid request: {error}"))
+}
+
+/// The non-JSON formats only have room for the main result
+fn render(response: Response, format: &str) -> Option<String> {
+    match (response, format) {
+        (Response::Genderified { text }, PLAIN) => Some(text),
+        (Response::Sentences(generated), PLAIN) => Some(PlainFormatter::default().format(&generated)),
+        (Response::Sentences(generated), MARKDOWN) => Some(DiscordFormatter::default().format(&generated)),
+        (Response::Parsed { short, .. }, PLAIN) => Some(short),
+        (Response::Card(card), PLAIN) => Some(card.render_text()),
+        (Response::Card(card), MARKDOWN) => Some(card.render_markdown()),
+        (Response::Svg { svg }, SVG) => Some(svg),
+        _ => None
+    }
+}
+
+async fn handle(server: Server, mut request: HttpRequest<Body>) -> HttpResponse<Body> {
+    // Start your timer!
+    let now = Instant::now();
+
+    let endpoint = match ENDPOINTS.iter().find(|endpoint| endpoint.path == request.uri().path()) {
+        Some(endpoint) => endpoint,
+        None => return reply_error(StatusCode::NOT_FOUND, JSON, format!("There's nothing at {}", request.uri().path()), now)
+    };
+    let accept = request.headers().get(ACCEPT).and_then(|accept| accept.to_str().ok());
+    let format = match negotiate(accept, endpoint.formats) {
+        Some(format) => format,
+        None => {
+            let formats: Vec<&str> = endpoint.formats.iter().map(|format| media_type(format)).collect();
+            return reply_error(StatusCode::NOT_ACCEPTABLE, JSON, format!("{} can reply with {}", endpoint.path, formats.join(", ")), now);
+        }
+    };
+    if request.method() != Method::POST {
+        let mut response = reply_error(StatusCode::METHOD_NOT_ALLOWED, format, format!("{} only takes POST", endpoint.path), now);
+        response.headers_mut().insert(ALLOW, HeaderValue::from_static("POST"));
+        return response;
+    }
+    let content_type = request.headers().get(CONTENT_TYPE).and_then(|content_type| content_type.to_str().ok());
+    if content_type.is_some_and(|content_type| !media_type(content_type).eq_ignore_ascii_case(JSON)) {
+        return reply_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, format, "Requests have to be JSON".to_owned(), now);
+    }
+    let too_long = request.headers().get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok())
+        .is_some_and(|length| length > MAX_FRAME_SIZE);
+    if too_long {
+        return reply_error(StatusCode::PAYLOAD_TOO_LARGE, format, format!("Requests can be at most {MAX_FRAME_SIZE} bytes"), now);
+    }
+
+    let data = match read_body(request.body_mut()).await {
+        Ok(data) => data,
+        Err(status) => return reply_error(status, format, format!("Couldn't read the request, they can be at most {MAX_FRAME_SIZE} bytes"), now)
+    };
+    if server.log_requests() {
+        println!("{}HTTP request to {}: {}", cs(), endpoint.path, String::from_utf8_lossy(&data));
+    }
+    let request = match to_request(&data, endpoint.command) {
+        Ok(request) => request,
+        Err(error) => return reply_error(StatusCode::BAD_REQUEST, format, error, now)
+    };
+    let id = request.request_id;
+    let result = server.dispatch(request.command).await;
+    println!("{}Processed HTTP request {}: {}ms", cs(), endpoint.path, now.elapsed().as_millis());
+
+    match result {
+        Ok(response) if format == JSON => reply_envelope(StatusCode::OK, &Envelope::new(id, Ok(response), now)),
+        Ok(response) => match render(response, format) {
+            Some(body) => reply(StatusCode::OK, format, body),
+            None => reply(StatusCode::INTERNAL_SERVER_ERROR, PLAIN, "That format isn't available for this")
+        },
+        Err(error) => {
+            let status = match error {
+                // The request was understood, but the engine couldn't do it
+                DispatchError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                DispatchError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+                DispatchError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR
+            };
+            match format {
+                JSON => reply_envelope(status, &Envelope::new(id, Err(error.to_string()), now)),
+                _ => reply(status, PLAIN, error.to_string())
+            }
+        }
+    }
+}
+
+/// Serves the HTTP API until something goes wrong with the listener
+pub async fn serve(server: Server, config: HttpConfig) {
+    let address: SocketAddr = match config.address.parse() {
+        Ok(address) => address,
+        Err(error) => {
+            println!("{}Couldn't use {} for HTTP: {error}", cs(), config.address);
+            return;
+        }
+    };
+    let service = make_service_fn(move |_| {
+        let server = server.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let server = server.clone();
+                async move { Ok::<_, Infallible>(handle(server, request).await) }
+            }))
+        }
+    });
+    let listener = match hyper::Server::try_bind(&address) {
+        Ok(listener) => listener,
+        Err(error) => {
+            println!("{}Couldn't bind HTTP to {address}: {error}", cs());
+            return;
+        }
+    };
+    println!("{}Accepting HTTP requests on {address}", cs());
+    if let Err(error) = listener.serve(service).await {
+        println!("{}HTTP server error: {error}", cs());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::engine::InferenceRules;
+    use crate::server::ServerConfig;
+    use crate::storage::SentenceStore;
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::sqlite::SqliteStore;
+
+    const FORMATS: &[&str] = &[JSON, PLAIN, MARKDOWN];
+
+    async fn status(store: Arc<dyn SentenceStore>, path: &str, body: &str) -> StatusCode {
+        let server = Server::new(store, InferenceRules::default(), &ServerConfig::default());
+        let request = HttpRequest::post(path).body(Body::from(body.to_owned())).unwrap();
+        handle(server, request).await.status()
+    }
+
+    #[test]
+    fn anything_goes_gets_the_first_format() {
+        assert_eq!(negotiate(None, FORMATS), Some(JSON));
+        assert_eq!(negotiate(Some(" "), FORMATS), Some(JSON));
+        assert_eq!(negotiate(Some("*/*"), FORMATS), Some(JSON));
+    }
+
+    #[test]
+    fn qualities_decide() {
+        assert_eq!(negotiate(Some("text/markdown"), FORMATS), Some(MARKDOWN));
+        assert_eq!(negotiate(Some("application/json;q=0.5, text/plain"), FORMATS), Some(PLAIN));
+        assert_eq!(negotiate(Some("text/*;q=0.8, application/json;q=0.9"), FORMATS), Some(JSON));
+        // Ties go to the endpoint's order
+        assert_eq!(negotiate(Some("text/*"), FORMATS), Some(PLAIN));
+        assert_eq!(negotiate(Some("TEXT/MARKDOWN; charset=utf-8"), FORMATS), Some(MARKDOWN));
+    }
+
+    #[test]
+    fn the_most_specific_range_counts() {
+        assert_eq!(negotiate(Some("text/*, text/plain;q=0"), FORMATS), Some(MARKDOWN));
+        assert_eq!(negotiate(Some("*/*;q=0.1, application/json;q=0"), FORMATS), Some(PLAIN));
+    }
+
+    #[test]
+    fn nothing_acceptable() {
+        assert_eq!(negotiate(Some("image/png"), FORMATS), None);
+        assert_eq!(negotiate(Some("*/*;q=0"), FORMATS), None);
+        assert_eq!(negotiate(Some("text/plain"), &[JSON, SVG]), None);
+    }
+
+    #[tokio::test]
+    async fn errors_get_their_own_status() {
+        let store: Arc<dyn SentenceStore> = Arc::new(MemoryStore::new());
+        assert_eq!(status(store.clone(), "/parse", r#"{"raw": "she/her"}"#).await, StatusCode::OK);
+        assert_eq!(status(store.clone(), "/parse", "{").await, StatusCode::BAD_REQUEST);
+        assert_eq!(status(store.clone(), "/parse", r#"{"raw": "a/b/c/d/e/f/g"}"#).await, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // A database that's lost its tables can't be used until someone fixes it
+        let path = std::env::temp_dir().join(format!("pronoun-engine-http-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let store: Arc<dyn SentenceStore> = Arc::new(SqliteStore::open(path).unwrap());
+        rusqlite::Connection::open(path).unwrap().execute("DROP TABLE Sentences", []).unwrap();
+        let body = r#"{"names": ["Ada"], "sets": []}"#;
+        assert_eq!(status(store, "/sentences", body).await, StatusCode::SERVICE_UNAVAILABLE);
+        let _ = std::fs::remove_file(path);
+    }
+}
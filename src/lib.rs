@@ -0,0 +1,22 @@
+// The engine itself, shared by the `pronoun_engine` binary, the bot and
+// anything else that wants to talk to it
+pub mod commands;
+pub mod shared;
+
+pub mod batch;
+pub mod card;
+pub mod client;
+pub mod config;
+pub mod engine;
+pub mod formatters;
+pub mod framing;
+pub mod http;
+pub mod library;
+pub mod locale;
+pub mod packs;
+pub mod protocol;
+pub mod sentences;
+pub mod server;
+pub mod share;
+pub mod storage;
+pub mod submissions;
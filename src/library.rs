@@ -0,0 +1,441 @@
+use std::collections::HashSet;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::locale::{DEFAULT_LANGUAGE, default_language, normalise_language};
+use crate::sentences::{SentenceType, lint_template, normalise_tag};
+use crate::storage::{Sentence, SentenceStore};
+
+/// How similar two sentences' words have to be to count as near duplicates
+const NEAR_DUPLICATE_THRESHOLD: f64 = 0.85;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LibraryFormat {
+    Json,
+    Csv,
+    Yaml
+}
+
+impl LibraryFormat {
+    /// Guesses the format from a file's extension
+    pub fn from_path(path: &str) -> Option<LibraryFormat> {
+        match path.rsplit('.').next()?.to_lowercase().as_str() {
+            "json" => Some(LibraryFormat::Json),
+            "csv" => Some(LibraryFormat::Csv),
+            "yaml" | "yml" => Some(LibraryFormat::Yaml),
+            _ => None
+        }
+    }
+}
+
+/// One sentence as it appears in an exported file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    #[serde(default)]
+    pub id: Option<u64>,
+    /// Only informational, imports work the type out from the text
+    #[serde(default, rename = "type")]
+    pub sentence_type: Option<String>,
+    pub text: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Whoever submitted it, if it came through a submission
+    #[serde(default)]
+    pub author: Option<String>,
+    /// English if left out
+    #[serde(default)]
+    pub language: Option<String>
+}
+
+// CSV has no lists, so tags are joined with commas (tags can't contain them)
+#[derive(Serialize, Deserialize)]
+struct CsvEntry {
+    id: Option<u64>,
+    #[serde(rename = "type")]
+    sentence_type: Option<String>,
+    text: String,
+    #[serde(default)]
+    tags: String,
+    author: Option<String>,
+    #[serde(default)]
+    language: Option<String>
+}
+
+pub fn write_entries(entries: &[LibraryEntry], format: LibraryFormat) -> Result<String, String> {
+    match format {
+        LibraryFormat::Json => serde_json::to_string_pretty(entries).map_err(|error| error.to_string()),
+        LibraryFormat::Yaml => serde_yaml::to_string(entries).map_err(|error| error.to_string()),
+        LibraryFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for entry in entries {
+                writer.serialize(CsvEntry {
+                    id: entry.id,
+                    sentence_type: entry.sentence_type.clone(),
+                    text: entry.text.clone(),
+                    tags: entry.tags.join(","),
+                    author: entry.author.clone(),
+                    language: entry.language.clone()
+                }).map_err(|error| error.to_string())?;
+            }
+            let bytes = writer.into_inner().map_err(|error| error.to_string())?;
+            String::from_utf8(bytes).map_err(|error| error.to_string())
+        }
+    }
+}
+
+pub fn read_entries(contents: &str, format: LibraryFormat) -> Result<Vec<LibraryEntry>, String> {
+    match format {
+        LibraryFormat::Json => serde_json::from_str(contents).map_err(|error| error.to_string()),
+        LibraryFormat::Yaml => serde_yaml::from_str(contents).map_err(|error| error.to_string()),
+        LibraryFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(contents.as_bytes());
+            let mut entries = Vec::new();
+            for record in reader.deserialize() {
+                let record: CsvEntry = record.map_err(|error| error.to_string())?;
+                entries.push(LibraryEntry {
+                    id: record.id,
+                    sentence_type: record.sentence_type,
+                    text: record.text,
+                    tags: record.tags.split(',').filter(|tag| !tag.trim().is_empty()).map(|tag| tag.to_owned()).collect(),
+                    author: record.author,
+                    language: record.language
+                });
+            }
+            Ok(entries)
+        }
+    }
+}
+
+/// Every sentence in the store, with whoever submitted it as the author.
+/// Sentences from packs are left out, they belong to the pack
+pub async fn export(store: &dyn SentenceStore) -> Result<Vec<LibraryEntry>, String> {
+    let mut sentences = store.list().await?;
+    sentences.retain(|sentence| sentence.pack.is_none());
+    sentences.sort_by_key(|sentence| sentence.id);
+    Ok(sentences.into_iter().map(|sentence| LibraryEntry {
+        id: Some(sentence.id),
+        sentence_type: Some(sentence.sentence_type.name().to_owned()),
+        author: sentence.submitter,
+        text: sentence.text,
+        tags: sentence.tags,
+        language: Some(sentence.language)
+    }).collect())
+}
+
+/// What importing one entry would do
+#[derive(Clone, Debug)]
+pub enum ImportAction {
+    Add {
+        sentence_type: SentenceType,
+        text: String,
+        tags: Vec<String>,
+        language: String
+    },
+    /// Already there with different tags
+    UpdateTags {
+        id: u64,
+        tags: Vec<String>
+    },
+    Duplicate {
+        id: u64
+    },
+    NearDuplicate {
+        id: u64,
+        existing: String,
+        similarity: f64
+    },
+    /// A duplicate of an earlier entry in the same file
+    RepeatedEntry,
+    Invalid {
+        reason: String
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportPlan {
+    pub steps: Vec<(LibraryEntry, ImportAction)>
+}
+
+// Whitespace and case don't make a sentence different
+fn normalise_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase()
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|char: char| !(char.is_alphanumeric() || "[]^".contains(char)))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_owned())
+        .collect()
+}
+
+/// How many words the two have in common out of all their words
+fn similarity(first: &HashSet<String>, second: &HashSet<String>) -> f64 {
+    let union = first.union(second).count();
+    match union {
+        0 => 1.0,
+        _ => first.intersection(second).count() as f64 / union as f64
+    }
+}
+
+/// Works out what importing would do without changing anything
+pub async fn plan_import(store: &dyn SentenceStore, entries: Vec<LibraryEntry>, keep_near_duplicates: bool) -> Result<ImportPlan, String> {
+    let existing: Vec<(Sentence, String, HashSet<String>)> = store.list().await?.into_iter().map(|sentence| {
+        let normalised = normalise_text(&sentence.text);
+        let words = words(&normalised);
+        (sentence, normalised, words)
+    }).collect();
+    let mut seen: HashSet<String> = HashSet::new();
+
+    let mut steps = Vec::new();
+    for entry in entries {
+        let action = plan_entry(&existing, &mut seen, &entry, keep_near_duplicates);
+        steps.push((entry, action));
+    }
+    Ok(ImportPlan { steps })
+}
+
+fn plan_entry(existing: &[(Sentence, String, HashSet<String>)], seen: &mut HashSet<String>, entry: &LibraryEntry, keep_near_duplicates: bool) -> ImportAction {
+    let text = entry.text.trim().to_owned();
+    let sentence_type = match lint_template(&text) {
+        Ok(sentence_type) => sentence_type,
+        Err(reason) => return ImportAction::Invalid { reason }
+    };
+    let mut tags: Vec<String> = Vec::new();
+    for tag in &entry.tags {
+        match normalise_tag(tag) {
+            Ok(tag) if !tags.contains(&tag) => tags.push(tag),
+            Ok(_) => (),
+            Err(reason) => return ImportAction::Invalid { reason }
+        }
+    }
+    let language = match entry.language.as_deref().map(normalise_language) {
+        Some(Ok(language)) => language,
+        Some(Err(reason)) => return ImportAction::Invalid { reason },
+        None => default_language()
+    };
+
+    let normalised = normalise_text(&text);
+    if !seen.insert(normalised.clone()) {
+        return ImportAction::RepeatedEntry;
+    }
+    if let Some((sentence, _, _)) = existing.iter().find(|(_, existing, _)| *existing == normalised) {
+        let mut current = sentence.tags.clone();
+        let mut wanted = tags.clone();
+        current.sort();
+        wanted.sort();
+        return match current == wanted {
+            true => ImportAction::Duplicate { id: sentence.id },
+            false => ImportAction::UpdateTags { id: sentence.id, tags }
+        };
+    }
+
+    if !keep_near_duplicates {
+        let entry_words = words(&normalised);
+        let closest = existing.iter()
+            .map(|(sentence, _, words)| (sentence, similarity(&entry_words, words)))
+            .max_by(|(_, first), (_, second)| first.total_cmp(second));
+        if let Some((sentence, similarity)) = closest {
+            if similarity >= NEAR_DUPLICATE_THRESHOLD {
+                return ImportAction::NearDuplicate { id: sentence.id, existing: sentence.text.clone(), similarity };
+            }
+        }
+    }
+
+    ImportAction::Add { sentence_type, text, tags, language }
+}
+
+impl ImportPlan {
+    /// A diff-like summary: `+` added, `~` changed, `=` skipped, `!` invalid
+    pub fn render(&self) -> String {
+        let mut lines = Vec::new();
+        let (mut added, mut changed, mut skipped, mut invalid) = (0, 0, 0, 0);
+        for (entry, action) in &self.steps {
+            lines.push(match action {
+                ImportAction::Add { tags, language, .. } => {
+                    added += 1;
+                    match language == DEFAULT_LANGUAGE {
+                        true => format!("+ {} [{}]", entry.text.trim(), tags.join(", ")),
+                        false => format!("+ {} [{}] ({language})", entry.text.trim(), tags.join(", "))
+                    }
+                }
+                ImportAction::UpdateTags { id, tags } => {
+                    changed += 1;
+                    format!("~ #{id} tags -> [{}]", tags.join(", "))
+                }
+                ImportAction::Duplicate { id } => {
+                    skipped += 1;
+                    format!("= {} (same as #{id})", entry.text.trim())
+                }
+                ImportAction::NearDuplicate { id, existing, similarity } => {
+                    skipped += 1;
+                    format!("= {} ({:.0}% like #{id}: {existing})", entry.text.trim(), similarity * 100.0)
+                }
+                ImportAction::RepeatedEntry => {
+                    skipped += 1;
+                    format!("= {} (repeated in this file)", entry.text.trim())
+                }
+                ImportAction::Invalid { reason } => {
+                    invalid += 1;
+                    format!("! {}: {}", entry.text.trim(), reason.replace('\n', " "))
+                }
+            });
+        }
+        lines.push(format!("\n{added} to add, {changed} to change, {skipped} skipped, {invalid} invalid"));
+        lines.join("\n")
+    }
+
+    /// Makes the changes, returns how many sentences were added or changed
+    pub async fn apply(&self, store: &dyn SentenceStore) -> Result<usize, String> {
+        let mut changes = 0;
+        for (entry, action) in &self.steps {
+            match action {
+                ImportAction::Add { sentence_type, text, tags, language } => {
+                    store.add(&Sentence {
+                        id: 0,
+                        sentence_type: *sentence_type,
+                        text: text.clone(),
+                        tags: tags.clone(),
+                        pack: None,
+                        language: language.clone(),
+                        submitter: entry.author.clone()
+                    }).await?;
+                    changes += 1;
+                }
+                ImportAction::UpdateTags { id, tags } => {
+                    store.set_tags(*id, tags).await?;
+                    changes += 1;
+                }
+                _ => ()
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// Moving the sentence library in and out of storage
+#[derive(Debug, clap::Subcommand)]
+pub enum SentencesCommand {
+    /// Write every sentence to a file, or stdout
+    Export {
+        /// Defaults to the output file's extension, then JSON
+        #[arg(long, value_enum)]
+        format: Option<LibraryFormat>,
+        #[arg(long, short)]
+        output: Option<String>
+    },
+    /// Check a file of sentences and show what importing it would change
+    Import {
+        file: String,
+        /// Defaults to the file's extension
+        #[arg(long, value_enum)]
+        format: Option<LibraryFormat>,
+        /// Actually make the changes instead of only showing them
+        #[arg(long)]
+        apply: bool,
+        /// Import sentences even if they're very similar to existing ones
+        #[arg(long)]
+        keep_near_duplicates: bool
+    }
+}
+
+impl SentencesCommand {
+    pub async fn run(self, store: &dyn SentenceStore) -> Result<(), String> {
+        match self {
+            SentencesCommand::Export { format, output } => {
+                let format = format
+                    .or_else(|| output.as_deref().and_then(LibraryFormat::from_path))
+                    .unwrap_or(LibraryFormat::Json);
+                let contents = write_entries(&export(store).await?, format)?;
+                match output {
+                    Some(output) => tokio::fs::write(&output, contents).await.map_err(|error| format!("{output}: {error}")),
+                    None => {
+                        println!("{contents}");
+                        Ok(())
+                    }
+                }
+            }
+            SentencesCommand::Import { file, format, apply, keep_near_duplicates } => {
+                let format = match format.or_else(|| LibraryFormat::from_path(&file)) {
+                    Some(format) => format,
+                    None => return Err(format!("Can't tell what format {file} is, use --format"))
+                };
+                let contents = tokio::fs::read_to_string(&file).await.map_err(|error| format!("{file}: {error}"))?;
+                let plan = plan_import(store, read_entries(&contents, format)?, keep_near_duplicates).await?;
+                println!("{}", plan.render());
+                if apply {
+                    println!("Made {} changes.", plan.apply(store).await?);
+                } else {
+                    println!("Nothing has been changed, pass --apply to make these changes.");
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+
+    async fn round_trip(format: LibraryFormat) {
+        let store = MemoryStore::new();
+        store.add(&Sentence {
+            id: 0,
+            sentence_type: SentenceType::NamesPronouns,
+            text: "[name] waved at [objective].".to_owned(),
+            tags: vec!["greeting".to_owned(), "short".to_owned()],
+            pack: None,
+            language: "pt-BR".to_owned(),
+            submitter: Some("ada".to_owned())
+        }).await.unwrap();
+        store.add(&Sentence {
+            id: 0,
+            sentence_type: SentenceType::PronounsOnly,
+            text: "[subjective] took the long way home.".to_owned(),
+            tags: vec![],
+            pack: None,
+            language: "en".to_owned(),
+            submitter: None
+        }).await.unwrap();
+
+        let contents = write_entries(&export(&store).await.unwrap(), format).unwrap();
+        let copy = MemoryStore::new();
+        let plan = plan_import(&copy, read_entries(&contents, format).unwrap(), false).await.unwrap();
+        assert_eq!(plan.apply(&copy).await.unwrap(), 2);
+
+        let original = store.list().await.unwrap();
+        let copied = copy.list().await.unwrap();
+        assert_eq!(copied.len(), original.len());
+        for (original, copied) in original.iter().zip(&copied) {
+            assert_eq!(copied.sentence_type, original.sentence_type);
+            assert_eq!(copied.text, original.text);
+            assert_eq!(copied.tags, original.tags);
+            assert_eq!(copied.language, original.language);
+            assert_eq!(copied.submitter, original.submitter);
+        }
+
+        // Importing it again changes nothing
+        let plan = plan_import(&copy, read_entries(&contents, format).unwrap(), false).await.unwrap();
+        assert_eq!(plan.apply(&copy).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn json_round_trips() {
+        round_trip(LibraryFormat::Json).await;
+    }
+
+    #[tokio::test]
+    async fn csv_round_trips() {
+        round_trip(LibraryFormat::Csv).await;
+    }
+
+    #[test]
+    fn csv_tags_can_be_left_out() {
+        let entries = read_entries("text,author\n[name] waved.,ada\n", LibraryFormat::Csv).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].tags.is_empty());
+        assert_eq!(entries[0].author.as_deref(), Some("ada"));
+    }
+}
@@ -0,0 +1,96 @@
+/// Sentences without a language are English, and every fallback chain ends here
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+// Built in intros, anything else falls back to English
+const INTROS: [(&str, &str); 6] = [
+    ("en", "Okay, how do these look?"),
+    ("de", "Okay, wie sieht das aus?"),
+    ("es", "Vale, ¿qué tal se ven estas?"),
+    ("fr", "D'accord, qu'est-ce que tu en penses ?"),
+    ("nl", "Oké, hoe zien deze eruit?"),
+    ("pt", "Certo, o que você acha destas?")
+];
+
+pub fn default_language() -> String {
+    DEFAULT_LANGUAGE.to_owned()
+}
+
+/// Tidies up a language tag like `pt_br` into `pt-BR`. Only the shape is
+/// checked, not whether the language exists
+pub fn normalise_language(tag: &str) -> Result<String, String> {
+    let invalid = || format!("`{tag}` isn't a language tag, they look like `en` or `pt-BR`");
+    let mut parts = Vec::new();
+    for (index, part) in tag.trim().split(['-', '_']).enumerate() {
+        if part.is_empty() || part.len() > 8 || !part.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid());
+        }
+        parts.push(match index {
+            0 if (2..=3).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphabetic()) => part.to_ascii_lowercase(),
+            0 => return Err(invalid()),
+            // Regions are upper case, scripts are title case
+            _ if part.len() == 2 && part.chars().all(|c| c.is_ascii_alphabetic()) => part.to_ascii_uppercase(),
+            _ if part.len() == 4 && part.chars().all(|c| c.is_ascii_alphabetic()) => part[..1].to_ascii_uppercase() + &part[1..].to_ascii_lowercase(),
+            _ => part.to_ascii_lowercase()
+        });
+    }
+    Ok(parts.join("-"))
+}
+
+/// The languages to try in order, e.g. `pt-BR`, `pt`, then `en`. Tags that
+/// can't be read go straight to the default
+pub fn fallback_chain(locale: &str) -> Vec<String> {
+    let mut chain = Vec::new();
+    if let Ok(locale) = normalise_language(locale) {
+        let mut tag = locale.as_str();
+        loop {
+            chain.push(tag.to_owned());
+            match tag.rfind('-') {
+                Some(end) => tag = &tag[..end],
+                None => break
+            }
+        }
+    }
+    if !chain.iter().any(|language| language == DEFAULT_LANGUAGE) {
+        chain.push(default_language());
+    }
+    chain
+}
+
+/// The intro used when a request doesn't give one
+pub fn default_intro(language: &str) -> &'static str {
+    fallback_chain(language).iter()
+        .find_map(|language| INTROS.iter().find(|(code, _)| code == language))
+        .map_or(INTROS[0].1, |(_, intro)| intro)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_tidied_up() {
+        assert_eq!(normalise_language("pt_br").unwrap(), "pt-BR");
+        assert_eq!(normalise_language(" EN ").unwrap(), "en");
+        assert_eq!(normalise_language("zh-hant-tw").unwrap(), "zh-Hant-TW");
+        for invalid in ["", "e", "english", "pt-", "pt--BR", "1n", "pt-BR!", "pt-verylongpart"] {
+            assert!(normalise_language(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn chains_end_in_the_default() {
+        assert_eq!(fallback_chain("pt_br"), ["pt-BR", "pt", "en"]);
+        assert_eq!(fallback_chain("en-GB"), ["en-GB", "en"]);
+        assert_eq!(fallback_chain("en"), ["en"]);
+        assert_eq!(fallback_chain("not a tag"), ["en"]);
+        assert_eq!(fallback_chain(""), ["en"]);
+    }
+
+    #[test]
+    fn intros_fall_back_too() {
+        assert_eq!(default_intro("pt_br"), "Certo, o que você acha destas?");
+        assert_eq!(default_intro("de-AT"), "Okay, wie sieht das aus?");
+        assert_eq!(default_intro("ja"), "Okay, how do these look?");
+        assert_eq!(default_intro("not a tag"), "Okay, how do these look?");
+    }
+}
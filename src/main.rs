@@ -1,148 +1,106 @@
-mod commands;
-mod shared;
-
-mod engine;
-mod sentences;
-mod socktest;
-
-use engine::{PronounSet, genderify_text, parse_set};
-use sentences::SentenceType;
-use mysql_async::Pool;
-use shared::console_stamp as cs;
-
-use once_cell::sync::Lazy;
-
-use std::env;
-use std::fs;
-use std::time::Instant;
-
-use serde::{Deserialize, Serialize};
-
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use tokio::net::{UnixStream, UnixListener};
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Config {
-    testing_mode: bool,
-    database: DBConfig,
-    engine_socket: String
-}
-#[derive(Debug, Serialize, Deserialize)]
-struct DBConfig {
-    address: String,
-    port: i32,
-    username: String,
-    password: String,
-    database: String,
-    salt: String
+use pronoun_engine::config::Config;
+use pronoun_engine::http;
+use pronoun_engine::library::SentencesCommand;
+use pronoun_engine::packs::PacksCommand;
+use pronoun_engine::server::Server;
+use pronoun_engine::shared::console_stamp as cs;
+use pronoun_engine::storage::{self, SentenceStore, StoreConfig};
+use pronoun_engine::storage::cache::CachedStore;
+
+use clap::{Parser, Subcommand};
+
+use std::sync::Arc;
+
+use tokio::net::UnixListener;
+
+#[derive(Debug, Parser)]
+#[command(name = "pronoun-engined", about = "The pronoun engine, and tools for looking after it")]
+struct Args {
+    /// A JSON, TOML or YAML config file, see `Config::load` for where it's
+    /// looked for otherwise
+    #[arg(long, global = true)]
+    config: Option<String>,
+    #[command(subcommand)]
+    command: Option<CliCommand>
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag="name")]
-#[serde(rename_all = "snake_case")]
-enum Command {
-    Genderify {
-        text: String,
-        names: Vec<String>,
-        sets: Vec<PronounSet>
-    },
-    Sentences(SentenceType),
-    Parse {
-        raw: String
-    }
+#[derive(Debug, Subcommand)]
+enum CliCommand {
+    /// Listen on the engine socket, this is what happens with no subcommand
+    Serve,
+    /// Import and export the sentence library
+    #[command(subcommand)]
+    Sentences(SentencesCommand),
+    /// Install, upgrade and remove sentence packs
+    #[command(subcommand)]
+    Packs(PacksCommand)
 }
 
-static CONFIG: Lazy<Config> = Lazy::new(|| {
-    let contents = fs::read_to_string("config.json")
-        .expect("Config file reading failed.");
-    serde_json::from_str(contents.as_str())
-        .expect("JSON deserialisation error.")
-});
-
-static DB: Lazy<Pool> = Lazy::new(|| {
-    let url = format!("mysql://{}:{}@{}:{}/{}", CONFIG.database.username, CONFIG.database.password, CONFIG.database.address, CONFIG.database.port, CONFIG.database.database);
-    mysql_async::Pool::new(url.as_str())
-});
-
-async fn handle_client(stream: UnixStream) {
-    let conn = match DB.get_conn().await {
-        Ok(conn) => conn,
+async fn serve(store: Arc<dyn SentenceStore>, config: Config) {
+    let socket_path = config.socket_path();
+    let listener = match UnixListener::bind(&socket_path) {
+        Ok(listener) => listener,
         Err(error) => {
-            print!("{}Error connecting to database: {error}", cs());
+            println!("{}Couldn't bind {}: {error}", cs(), socket_path.display());
             return;
         }
     };
 
-    let (mut reader, mut writer) = io::split(stream);
-
-    loop {
-        // Start your timer!
-        let now = Instant::now();
-
-        let mut buf = vec![0; 128];
-        let mut full_data = Vec::<u8>::new();
-
-        loop {
-            let n = reader.read(&mut buf).await.unwrap();
-            full_data.append(&mut buf);
-            if n == 0 {
-                break;
-            }
-        }
-
-        let command: Command = match serde_json::from_slice(&full_data) {
-            Ok(command) => command,
-            Err(error) => {
-                // We should send back a signal that the input was invalid
-                println!("{}Error at {}ms: {error}", cs(), now.elapsed().as_millis());
-                return;
-            }
-        };
-
-        match command {
-            Command::Genderify { text, names, sets } => {
-                println!("gender");
-            },
-            Command::Sentences(t) => {
-                println!("setences");
-            },
-            Command::Parse { raw } => {
-                println!("parse");
-            }
-        }
-
-        // How long did it take?
-        println!("{}{}: {}ms", cs(), "Processed request", now.elapsed().as_millis());
+    let server = Server::new(store, config.inference, &config.server);
+    if let Some(http_config) = config.http {
+        tokio::spawn(http::serve(server.clone(), http_config));
     }
+    server.run(listener).await
 }
 
 #[tokio::main]
 async fn main() {
-    let socket_path = match env::var("XDG_RUNTIME_DIR") {
-        Ok(var) => var,
+    let args = Args::parse();
+
+    let mut config = match Config::load(args.config.as_deref()).and_then(|config| config.validate().map(|_| config)) {
+        Ok(config) => config,
         Err(error) => {
-            println!("{}Couldn't get run directory environment variable: {error}", cs());
-            return;
+            eprintln!("Couldn't load the config:\n{error}");
+            std::process::exit(1);
         }
-    } + "/" + &CONFIG.engine_socket;
+    };
+    if config.testing_mode {
+        println!("{}Testing mode: sentences are kept in memory and every request is logged", cs());
+        config.storage = StoreConfig::Memory {};
+        config.server.log_requests = true;
+    }
 
-    let listener = match UnixListener::bind(socket_path) {
-        Ok(listener) => listener,
+    let store: Arc<dyn SentenceStore> = match storage::open(&config.storage, config.database.opts()).await {
+        Ok(store) => Arc::from(store),
         Err(error) => {
-            println!("{}Could bind socket: {error}", cs());
+            println!("{}Couldn't open sentence storage: {error}", cs());
             return;
         }
     };
 
-    println!("{}Accepting connections now!", cs());
-    loop {
-        match listener.accept().await {
-            Ok((stream, _addr)) => {
-                println!("{}New client!", cs());
-                handle_client(stream).await;
+    match args.command {
+        None | Some(CliCommand::Serve) => {
+            if !config.cache.enabled {
+                return serve(store, config).await;
             }
-            Err(error) => {
-                println!("{}Connection error: {error}", cs());
+            match CachedStore::new(store).await {
+                Ok(cached) => {
+                    cached.spawn_refresh(&config.cache);
+                    serve(Arc::new(cached), config).await
+                }
+                Err(error) => println!("{}Couldn't load sentences: {error}", cs())
+            }
+        }
+        Some(CliCommand::Sentences(command)) => {
+            if let Err(error) = command.run(store.as_ref()).await {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        }
+        Some(CliCommand::Packs(command)) => {
+            if let Err(error) = command.run(store.as_ref()).await {
+                eprintln!("{error}");
+                std::process::exit(1);
             }
         }
     }
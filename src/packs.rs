@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+
+use crate::locale::normalise_language;
+use crate::protocol::DispatchError;
+use crate::sentences::{lint_template, normalise_tag};
+use crate::shared::console_stamp as cs;
+use crate::storage::{Sentence, SentenceStore};
+
+/// Everything about a pack except its sentences, this is what gets stored
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackManifest {
+    /// Lowercase letters, digits and dashes, e.g. `fantasy-roleplay`
+    pub name: String,
+    /// Dot separated numbers, e.g. `1.2.0`
+    pub version: String,
+    pub author: String,
+    /// A language tag like `en` or `pt-BR`
+    pub language: String,
+    pub license: String,
+    #[serde(default)]
+    pub description: Option<String>
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackSentence {
+    pub text: String,
+    #[serde(default)]
+    pub tags: Vec<String>
+}
+
+/// A pack as it's written in a file, the manifest fields sit alongside the
+/// sentences
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SentencePack {
+    #[serde(flatten)]
+    pub manifest: PackManifest,
+    pub sentences: Vec<PackSentence>
+}
+
+/// Turns `1.2.0` into `[1, 2, 0]` so versions compare properly
+pub fn parse_version(version: &str) -> Result<Vec<u64>, String> {
+    version.split('.').map(|part| part.parse::<u64>()).collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("\"{version}\" isn't a version, it should look like 1.2.0"))
+}
+
+impl SentencePack {
+    /// Reads a pack from JSON or YAML, going by the file's extension
+    pub fn read(path: &str, contents: &str) -> Result<SentencePack, String> {
+        let pack: SentencePack = match path.rsplit('.').next().map(|extension| extension.to_lowercase()).as_deref() {
+            Some("json") => serde_json::from_str(contents).map_err(|error| format!("{path}: {error}"))?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(contents).map_err(|error| format!("{path}: {error}"))?,
+            _ => return Err(format!("Can't tell what format {path} is, packs are JSON or YAML"))
+        };
+        pack.validate()?;
+        Ok(pack)
+    }
+
+    /// Checks the manifest and lints every template, listing every problem
+    /// rather than stopping at the first
+    pub fn validate(&self) -> Result<(), String> {
+        let manifest = &self.manifest;
+        let mut problems = Vec::new();
+        if manifest.name.is_empty() || !manifest.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+            problems.push(format!("\"{}\" can't be a pack name, use lowercase letters, digits and dashes", manifest.name));
+        }
+        if let Err(error) = parse_version(&manifest.version) {
+            problems.push(error);
+        }
+        if let Err(error) = normalise_language(&manifest.language) {
+            problems.push(error);
+        }
+        for (field, value) in [("author", &manifest.author), ("license", &manifest.license)] {
+            if value.trim().is_empty() {
+                problems.push(format!("The pack's {field} can't be empty"));
+            }
+        }
+        if self.sentences.is_empty() {
+            problems.push("The pack doesn't have any sentences".to_owned());
+        }
+        for (index, sentence) in self.sentences.iter().enumerate() {
+            if let Err(error) = lint_template(&sentence.text) {
+                problems.push(format!("Sentence {}: {error}", index + 1));
+            }
+            for tag in &sentence.tags {
+                if let Err(error) = normalise_tag(tag) {
+                    problems.push(format!("Sentence {}: {error}", index + 1));
+                }
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems.join("\n"))
+        }
+    }
+}
+
+fn to_sentences(pack: &SentencePack) -> Result<Vec<Sentence>, String> {
+    let language = normalise_language(&pack.manifest.language)?;
+    pack.sentences.iter().map(|sentence| {
+        // Already linted, this just gets the type
+        let sentence_type = lint_template(&sentence.text)?;
+        // `Formal` and `formal` are the same tag once normalised
+        let mut tags = sentence.tags.iter().map(|tag| normalise_tag(tag)).collect::<Result<Vec<_>, _>>()?;
+        tags.sort();
+        tags.dedup();
+        Ok(Sentence {
+            id: 0,
+            sentence_type,
+            text: sentence.text.clone(),
+            tags,
+            pack: Some(pack.manifest.name.clone()),
+            language: language.clone(),
+            submitter: None
+        })
+    }).collect()
+}
+
+// Adds all of the sentences and the manifest or none of them, anything
+// already added is removed again if something fails
+async fn add_sentences(store: &dyn SentenceStore, manifest: &PackManifest, sentences: &[Sentence]) -> Result<(), String> {
+    let mut added = Vec::new();
+    let mut result = Ok(());
+    for sentence in sentences {
+        match store.add(sentence).await {
+            Ok(id) => added.push(id),
+            Err(error) => {
+                result = Err(error);
+                break;
+            }
+        }
+    }
+    if result.is_ok() {
+        result = store.save_pack(manifest).await;
+    }
+    if result.is_err() {
+        for id in added {
+            let _ = store.remove(id).await;
+        }
+    }
+    result
+}
+
+async fn installed(store: &dyn SentenceStore, name: &str) -> Result<Option<PackManifest>, String> {
+    Ok(store.packs().await?.into_iter().find(|manifest| manifest.name == name))
+}
+
+pub async fn install(store: &dyn SentenceStore, pack: &SentencePack) -> Result<(), String> {
+    pack.validate()?;
+    if let Some(existing) = installed(store, &pack.manifest.name).await? {
+        return Err(format!("{} {} is already installed, upgrade it instead", existing.name, existing.version));
+    }
+    add_sentences(store, &pack.manifest, &to_sentences(pack)?).await
+}
+
+/// Replaces an installed pack's sentences with the new version's. Going to
+/// the same or an older version needs `force`. If the new version can't be
+/// added the old one is put back
+pub async fn upgrade(store: &dyn SentenceStore, pack: &SentencePack, force: bool) -> Result<PackManifest, String> {
+    pack.validate()?;
+    let existing = match installed(store, &pack.manifest.name).await? {
+        Some(existing) => existing,
+        None => return Err(format!("{} isn't installed, install it instead", pack.manifest.name))
+    };
+    // An unreadable installed version shouldn't stop it being replaced
+    let newer = match parse_version(&existing.version) {
+        Ok(old) => parse_version(&pack.manifest.version)? > old,
+        Err(_) => true
+    };
+    if !newer && !force {
+        return Err(format!("{} {} is installed, which isn't older than {}", existing.name, existing.version, pack.manifest.version));
+    }
+    let sentences = to_sentences(pack)?;
+    let old: Vec<Sentence> = store.list().await?.into_iter()
+        .filter(|sentence| sentence.pack.as_ref() == Some(&existing.name))
+        .collect();
+    store.remove_pack(&existing.name).await?;
+    if let Err(error) = add_sentences(store, &pack.manifest, &sentences).await {
+        return match add_sentences(store, &existing, &old).await {
+            Ok(()) => Err(error),
+            Err(restoring) => Err(format!("{error}\nThe old version couldn't be put back either: {restoring}"))
+        };
+    }
+    Ok(existing)
+}
+
+pub async fn remove(store: &dyn SentenceStore, name: &str) -> Result<(), String> {
+    match store.remove_pack(name).await? {
+        true => Ok(()),
+        false => Err(format!("{name} isn't installed"))
+    }
+}
+
+/// Packs given with the request win, then the guild's, then none. Names
+/// that aren't installed are an error so typos don't go unnoticed, but
+/// guild packs that can't be loaded count as none
+pub async fn resolve_packs(store: &dyn SentenceStore, packs: Option<Vec<String>>, guild: Option<&str>) -> Result<Vec<String>, DispatchError> {
+    let packs = match (packs, guild) {
+        (Some(packs), _) => packs,
+        (None, Some(guild)) => return Ok(match store.guild_packs(guild).await {
+            Ok(packs) => packs.unwrap_or_default(),
+            Err(error) => {
+                println!("{}Couldn't load guild {guild}'s packs, using none: {error}", cs());
+                Vec::new()
+            }
+        }),
+        (None, None) => return Ok(Vec::new())
+    };
+    let available = store.packs().await.map_err(DispatchError::Unavailable)?;
+    for pack in &packs {
+        if !available.iter().any(|manifest| &manifest.name == pack) {
+            return Err(DispatchError::Invalid(format!("There's no pack called {pack}")));
+        }
+    }
+    Ok(packs)
+}
+
+/// Looking after installed sentence packs
+#[derive(Debug, clap::Subcommand)]
+pub enum PacksCommand {
+    /// Install a pack from a JSON or YAML file
+    Install {
+        file: String
+    },
+    /// Replace an installed pack with a newer version
+    Upgrade {
+        file: String,
+        /// Replace it even if the file's version isn't newer
+        #[arg(long)]
+        force: bool
+    },
+    /// Remove a pack and all of its sentences
+    Remove {
+        name: String
+    },
+    /// Show every installed pack
+    List
+}
+
+async fn read_pack(file: &str) -> Result<SentencePack, String> {
+    let contents = tokio::fs::read_to_string(file).await.map_err(|error| format!("{file}: {error}"))?;
+    SentencePack::read(file, &contents)
+}
+
+impl PacksCommand {
+    pub async fn run(self, store: &dyn SentenceStore) -> Result<(), String> {
+        match self {
+            PacksCommand::Install { file } => {
+                let pack = read_pack(&file).await?;
+                install(store, &pack).await?;
+                println!("Installed {} {} ({} sentences).", pack.manifest.name, pack.manifest.version, pack.sentences.len());
+            }
+            PacksCommand::Upgrade { file, force } => {
+                let pack = read_pack(&file).await?;
+                let old = upgrade(store, &pack, force).await?;
+                println!("Upgraded {} from {} to {} ({} sentences).", pack.manifest.name, old.version, pack.manifest.version, pack.sentences.len());
+            }
+            PacksCommand::Remove { name } => {
+                remove(store, &name).await?;
+                println!("Removed {name}.");
+            }
+            PacksCommand::List => {
+                let packs = store.packs().await?;
+                if packs.is_empty() {
+                    println!("No packs are installed.");
+                }
+                for manifest in packs {
+                    println!("{} {} [{}] by {}, {}", manifest.name, manifest.version, manifest.language, manifest.author, manifest.license);
+                    if let Some(description) = manifest.description {
+                        println!("    {description}");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::sentences::{SentenceType, TagFilter};
+    use crate::storage::memory::MemoryStore;
+    use crate::submissions::{Submission, SubmissionStatus};
+
+    fn pack(version: &str, sentences: &[(&str, &[&str])]) -> SentencePack {
+        SentencePack {
+            manifest: PackManifest {
+                name: "test".to_owned(),
+                version: version.to_owned(),
+                author: "Someone".to_owned(),
+                language: "en".to_owned(),
+                license: "CC0".to_owned(),
+                description: None
+            },
+            sentences: sentences.iter().map(|(text, tags)| PackSentence {
+                text: text.to_string(),
+                tags: tags.iter().map(|tag| tag.to_string()).collect()
+            }).collect()
+        }
+    }
+
+    // Fails one `add`, counting from 1
+    struct Flaky {
+        inner: MemoryStore,
+        adds: AtomicUsize,
+        failing: usize
+    }
+
+    #[async_trait]
+    impl SentenceStore for Flaky {
+        async fn fetch(&self, sentence_type: SentenceType) -> Result<Vec<Sentence>, String> { self.inner.fetch(sentence_type).await }
+        async fn add(&self, sentence: &Sentence) -> Result<u64, String> {
+            match self.adds.fetch_add(1, Ordering::SeqCst) + 1 == self.failing {
+                true => Err("The database went away".to_owned()),
+                false => self.inner.add(sentence).await
+            }
+        }
+        async fn remove(&self, id: u64) -> Result<bool, String> { self.inner.remove(id).await }
+        async fn list(&self) -> Result<Vec<Sentence>, String> { self.inner.list().await }
+        async fn set_tags(&self, id: u64, tags: &[String]) -> Result<bool, String> { self.inner.set_tags(id, tags).await }
+        async fn history(&self, requester: &str, limit: usize) -> Result<Vec<u64>, String> { self.inner.history(requester, limit).await }
+        async fn record_history(&self, requester: &str, ids: &[u64], keep: usize) -> Result<(), String> { self.inner.record_history(requester, ids, keep).await }
+        async fn add_submission(&self, submission: &Submission) -> Result<u64, String> { self.inner.add_submission(submission).await }
+        async fn update_submission(&self, submission: &Submission, from: SubmissionStatus) -> Result<bool, String> { self.inner.update_submission(submission, from).await }
+        async fn submission(&self, id: u64) -> Result<Option<Submission>, String> { self.inner.submission(id).await }
+        async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String> { self.inner.submissions(status).await }
+        async fn packs(&self) -> Result<Vec<PackManifest>, String> { self.inner.packs().await }
+        async fn save_pack(&self, manifest: &PackManifest) -> Result<(), String> { self.inner.save_pack(manifest).await }
+        async fn remove_pack(&self, name: &str) -> Result<bool, String> { self.inner.remove_pack(name).await }
+        async fn guild_filter(&self, guild: &str) -> Result<Option<TagFilter>, String> { self.inner.guild_filter(guild).await }
+        async fn set_guild_filter(&self, guild: &str, filter: Option<&TagFilter>) -> Result<(), String> { self.inner.set_guild_filter(guild, filter).await }
+        async fn guild_packs(&self, guild: &str) -> Result<Option<Vec<String>>, String> { self.inner.guild_packs(guild).await }
+        async fn set_guild_packs(&self, guild: &str, packs: Option<&[String]>) -> Result<(), String> { self.inner.set_guild_packs(guild, packs).await }
+    }
+
+    #[tokio::test]
+    async fn duplicate_tags_are_merged() {
+        let store = MemoryStore::new();
+        install(&store, &pack("1.0.0", &[("[name] waved.", &["Formal", "formal", " cute "])])).await.unwrap();
+        let sentences = store.list().await.unwrap();
+        assert_eq!(sentences.len(), 1);
+        assert_eq!(sentences[0].tags, vec!["cute", "formal"]);
+    }
+
+    #[tokio::test]
+    async fn failed_installs_leave_nothing_behind() {
+        let store = Flaky { inner: MemoryStore::new(), adds: AtomicUsize::new(0), failing: 2 };
+        let result = install(&store, &pack("1.0.0", &[("[name] waved.", &[]), ("[name] sat down.", &[])])).await;
+        assert!(result.is_err());
+        assert!(store.list().await.unwrap().is_empty());
+        assert!(store.packs().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_upgrades_keep_the_old_version() {
+        // The install is the first add, then the upgrade's third sentence fails
+        let store = Flaky { inner: MemoryStore::new(), adds: AtomicUsize::new(0), failing: 4 };
+        install(&store, &pack("1.0.0", &[("[name] waved.", &["formal"])])).await.unwrap();
+        let new = pack("2.0.0", &[("[name] sat down.", &[]), ("[name] stood up.", &[]), ("[name] left.", &[])]);
+        let result = upgrade(&store, &new, false).await;
+        assert!(result.is_err());
+        let sentences = store.list().await.unwrap();
+        assert_eq!(sentences.iter().map(|sentence| sentence.text.as_str()).collect::<Vec<_>>(), vec!["[name] waved."]);
+        assert_eq!(sentences[0].tags, vec!["formal"]);
+        assert_eq!(store.packs().await.unwrap()[0].version, "1.0.0");
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::batch::{BatchItem, BatchResult, genderify_batch};
+use crate::card::{PronounCard, Theme};
+use crate::engine::{InferenceRules, InferredSet, PronounSet, genderify_text, parse_set_with};
+use crate::locale::DEFAULT_LANGUAGE;
+use crate::packs::{PackManifest, resolve_packs};
+use crate::sentences::{GeneratedSentences, SelectionMode, SentenceOptions, TagFilter, generate_sentences, resolve_filter};
+use crate::storage::SentenceStore;
+use crate::submissions::{self, Review, Submission, SubmissionStatus};
+
+/// Bumped whenever a command or response changes shape
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest version still understood
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Every command's `name`, for the handshake
+pub const COMMANDS: [&str; 14] = [
+    "hello", "genderify", "genderify_batch", "sentences", "guild_filter", "guild_packs", "packs",
+    "refresh_sentences", "parse", "card", "card_svg", "submit", "review", "submissions"
+];
+
+/// Optional things a client can check for before relying on them
+pub const FEATURES: [&str; 7] = [
+    "request_ids", "pipelining", "svg_cards", "genderify_batch", "sentence_packs", "locales", "submissions"
+];
+
+/// Everything that can be asked of the engine
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag="name")]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    /// Has to be the first thing sent on a socket connection
+    Hello {
+        version: u32,
+        /// Features the client would like, any missing are listed in the reply
+        #[serde(default)]
+        features: Vec<String>
+    },
+    Genderify {
+        text: String,
+        names: Vec<String>,
+        sets: Vec<PronounSet>
+    },
+    /// Many texts at once, each one can override the shared names and sets
+    GenderifyBatch {
+        items: Vec<BatchItem>,
+        #[serde(default)]
+        names: Vec<String>,
+        #[serde(default)]
+        sets: Vec<PronounSet>,
+        /// Spread the work over every core
+        #[serde(default)]
+        parallel: bool
+    },
+    Sentences {
+        names: Vec<String>,
+        sets: Vec<PronounSet>,
+        /// Falls back to the guild's default filter when left out
+        filter: Option<TagFilter>,
+        /// Falls back to the guild's packs when left out
+        packs: Option<Vec<String>>,
+        guild: Option<String>,
+        /// Any stable ID for the user, so they aren't shown the same sentences
+        user: Option<String>,
+        #[serde(default)]
+        mode: SelectionMode,
+        count: Option<usize>,
+        /// A language tag like `pt-BR`, English if left out
+        locale: Option<String>,
+        intro: Option<String>,
+        outro: Option<String>
+    },
+    GuildFilter {
+        guild: String,
+        /// `None` clears the guild's default
+        filter: Option<TagFilter>
+    },
+    GuildPacks {
+        guild: String,
+        /// `None` goes back to no packs
+        packs: Option<Vec<String>>
+    },
+    /// Lists the installed packs
+    Packs,
+    /// Reloads cached sentences now instead of waiting for the next refresh
+    RefreshSentences,
+    Parse {
+        raw: String
+    },
+    /// A card's contents, for drawing it some other way than as an SVG
+    Card {
+        names: Vec<String>,
+        sets: Vec<PronounSet>
+    },
+    CardSvg {
+        names: Vec<String>,
+        sets: Vec<PronounSet>,
+        #[serde(default)]
+        theme: Theme
+    },
+    Submit {
+        text: String,
+        #[serde(default)]
+        tags: Vec<String>,
+        /// English if left out
+        language: Option<String>,
+        submitter: String
+    },
+    Review {
+        id: u64,
+        moderator: String,
+        review: Review
+    },
+    /// Leaving out the status lists every submission
+    Submissions {
+        status: Option<SubmissionStatus>
+    }
+}
+
+/// Chosen by the client and sent back with the reply, so replies can be
+/// matched up when they come back in a different order
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(u64),
+    Text(String)
+}
+
+/// A command as it's sent, the ID sits alongside the command's own fields.
+/// It's `request_id` rather than `id` since some commands have an `id` already
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<RequestId>,
+    #[serde(flatten)]
+    pub command: Command
+}
+
+impl Request {
+    /// Reads a request, holding on to the ID even if the rest is wrong so the
+    /// error can still be matched up
+    pub fn parse(data: &[u8]) -> (Option<RequestId>, Result<Request, String>) {
+        let value: serde_json::Value = match serde_json::from_slice(data) {
+            Ok(value) => value,
+            Err(error) => return (None, Err(format!("Invalid request: {error}")))
+        };
+        let id = value.get("request_id").and_then(|id| serde_json::from_value(id.clone()).ok());
+        let request = serde_json::from_value(value).map_err(|error| format!("Invalid request: {error}"));
+        (id, request)
+    }
+}
+
+/// The engine's side of the handshake
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Handshake {
+    pub version: u32,
+    pub min_version: u32,
+    /// The engine's own version
+    pub server: String,
+    pub commands: Vec<String>,
+    pub features: Vec<String>,
+    /// Features the client asked for that this engine doesn't have
+    pub missing: Vec<String>
+}
+
+/// What a command gives back, tagged with `type` so clients know what to
+/// expect without remembering what they asked for
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag="type")]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Hello(Handshake),
+    Genderified {
+        text: String
+    },
+    /// In the same order as the batch's items
+    GenderifiedBatch {
+        results: Vec<BatchResult>
+    },
+    Sentences(GeneratedSentences),
+    Parsed {
+        /// The shortest way to write the set
+        short: String,
+        #[serde(flatten)]
+        parsed: InferredSet
+    },
+    Card(PronounCard),
+    Svg {
+        svg: String
+    },
+    Submission(Submission),
+    Submissions {
+        submissions: Vec<Submission>
+    },
+    Packs {
+        packs: Vec<PackManifest>
+    },
+    /// For commands that only change something
+    Done
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Error
+}
+
+/// Every reply looks like this, with exactly one of `result` and `error`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    /// The request's ID, if it had one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<RequestId>,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Response>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// How long the engine spent on the request
+    pub elapsed_ms: f64
+}
+
+impl Envelope {
+    /// `started` should be when the request was received
+    pub fn new(request_id: Option<RequestId>, result: Result<Response, String>, started: Instant) -> Envelope {
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        match result {
+            Ok(response) => Envelope { request_id, status: Status::Ok, result: Some(response), error: None, elapsed_ms },
+            Err(error) => Envelope { request_id, status: Status::Error, result: None, error: Some(error), elapsed_ms }
+        }
+    }
+}
+
+/// Why a command couldn't be done, so HTTP can answer with the right status
+#[derive(Debug)]
+pub enum DispatchError {
+    /// Something about the request is wrong, sending it again won't help
+    Invalid(String),
+    /// Storage couldn't be reached, trying again later might work
+    Unavailable(String),
+    /// The engine itself went wrong
+    Internal(String)
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DispatchError::Invalid(message) | DispatchError::Unavailable(message) | DispatchError::Internal(message) => formatter.write_str(message)
+        }
+    }
+}
+
+/// Checks the client speaks a version we understand and says what we can do
+pub fn hello(version: u32, features: &[String]) -> Result<Response, String> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Err(format!(
+            "This engine speaks protocol versions {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}, but the client speaks version {version}. Update whichever is older."
+        ));
+    }
+    Ok(Response::Hello(Handshake {
+        version: PROTOCOL_VERSION,
+        min_version: MIN_PROTOCOL_VERSION,
+        server: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        commands: COMMANDS.iter().map(|command| command.to_string()).collect(),
+        features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+        missing: features.iter().filter(|feature| !FEATURES.contains(&feature.as_str())).cloned().collect()
+    }))
+}
+
+// For commands that are all CPU, so a big one doesn't hold up everything
+// else waiting on the same worker
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> Result<T, DispatchError> {
+    tokio::task::spawn_blocking(work).await.map_err(|error| DispatchError::Internal(error.to_string()))
+}
+
+pub async fn dispatch(command: Command, store: &dyn SentenceStore, rules: &InferenceRules) -> Result<Response, DispatchError> {
+    Ok(match command {
+        Command::Hello { version, features } => hello(version, &features).map_err(DispatchError::Invalid)?,
+        Command::Genderify { text, names, sets } => Response::Genderified { text: blocking(move || genderify_text(&text, names, sets)).await? },
+        Command::GenderifyBatch { items, names, sets, parallel } => Response::GenderifiedBatch { results: genderify_batch(items, names, sets, parallel).await? },
+        Command::Sentences { names, sets, filter, packs, guild, user, mode, count, locale, intro, outro } => {
+            let defaults = SentenceOptions::default();
+            let options = SentenceOptions {
+                filter: resolve_filter(store, filter, guild.as_deref()).await,
+                packs: resolve_packs(store, packs, guild.as_deref()).await?,
+                requester: user,
+                mode,
+                count: count.unwrap_or(defaults.count),
+                locale,
+                intro,
+                outro: outro.unwrap_or(defaults.outro)
+            };
+            Response::Sentences(generate_sentences(names, sets, store, &options).await?)
+        }
+        Command::GuildFilter { guild, filter } => {
+            store.set_guild_filter(&guild, filter.as_ref()).await.map_err(DispatchError::Unavailable)?;
+            Response::Done
+        }
+        Command::GuildPacks { guild, packs } => {
+            match resolve_packs(store, packs, None).await? {
+                packs if packs.is_empty() => store.set_guild_packs(&guild, None).await,
+                packs => store.set_guild_packs(&guild, Some(&packs)).await
+            }.map_err(DispatchError::Unavailable)?;
+            Response::Done
+        }
+        Command::Packs => Response::Packs { packs: store.packs().await.map_err(DispatchError::Unavailable)? },
+        Command::RefreshSentences => {
+            store.refresh().await.map_err(DispatchError::Unavailable)?;
+            Response::Done
+        }
+        Command::Parse { raw } => {
+            let parsed = parse_set_with(&raw, rules).map_err(|error| DispatchError::Invalid(error.to_owned()))?;
+            Response::Parsed { short: parsed.set.short(), parsed }
+        }
+        Command::Card { names, sets } => Response::Card(PronounCard::new(&sets, &names).map_err(|error| DispatchError::Invalid(error.to_owned()))?),
+        Command::CardSvg { names, sets, theme } => {
+            let card = PronounCard::new(&sets, &names).map_err(|error| DispatchError::Invalid(error.to_owned()))?;
+            Response::Svg { svg: blocking(move || card.render_svg(theme)).await? }
+        }
+        Command::Submit { text, tags, language, submitter } => {
+            let language = language.as_deref().unwrap_or(DEFAULT_LANGUAGE);
+            Response::Submission(submissions::submit(store, &text, &tags, language, &submitter).await?)
+        }
+        Command::Review { id, moderator, review } => Response::Submission(submissions::review(store, id, &moderator, review).await?),
+        Command::Submissions { status } => Response::Submissions { submissions: store.submissions(status).await.map_err(DispatchError::Unavailable)? }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hello_checks_the_version() {
+        assert!(hello(MIN_PROTOCOL_VERSION - 1, &[]).is_err());
+        assert!(hello(PROTOCOL_VERSION + 1, &[]).is_err());
+        match hello(PROTOCOL_VERSION, &["pipelining".to_owned(), "telepathy".to_owned()]) {
+            Ok(Response::Hello(handshake)) => assert_eq!(handshake.missing, ["telepathy"]),
+            other => panic!("Expected a handshake, got {other:?}")
+        }
+    }
+}
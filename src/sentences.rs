@@ -1,10 +1,13 @@
-use mysql_async::{Conn, prelude::{Queryable}};
-use rand::Rng;
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 
-use crate::engine::{PronounSet, genderify_text};
+use crate::engine::{Form, PronounSet, genderify_text, template_placeholders};
+use crate::locale::{DEFAULT_LANGUAGE, default_intro, fallback_chain};
+use crate::protocol::DispatchError;
+use crate::shared::console_stamp as cs;
+use crate::storage::{CompiledSentence, Sentence, SentenceStore};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(tag="type")]
 pub enum SentenceType {
     Invalid = 255,
@@ -13,40 +16,352 @@ pub enum SentenceType {
     NamesOnly = 2
 }
 
-// Ahhh, good old `generate_sentences`, like the return of an old friend
-pub async fn generate_sentences(names: Vec<String>, sets: Vec<PronounSet>, mut db: Conn, before: &str, after: &str) -> Result<String, String> {
-    if sets.len() == 0 && names.len() == 0 {
-        return Ok("Can't make sentences with no names or pronouns :(".to_owned());
+impl SentenceType {
+    /// The number this type is stored as
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SentenceType::Invalid => "Invalid",
+            SentenceType::NamesPronouns => "NamesPronouns",
+            SentenceType::PronounsOnly => "PronounsOnly",
+            SentenceType::NamesOnly => "NamesOnly"
+        }
+    }
+
+    pub fn from_id(id: u8) -> SentenceType {
+        match id {
+            0 => SentenceType::NamesPronouns,
+            1 => SentenceType::PronounsOnly,
+            2 => SentenceType::NamesOnly,
+            _ => SentenceType::Invalid
+        }
+    }
+}
+
+/// The longest template that can be submitted
+const MAX_TEMPLATE_LENGTH: usize = 500;
+
+/// Checks a template for mistakes and works out which type it is from the
+/// placeholders it uses. Every problem found is listed in the error
+pub fn lint_template(text: &str) -> Result<SentenceType, String> {
+    let mut problems = Vec::new();
+    if text.trim().is_empty() {
+        return Err("Sentences can't be empty.".to_owned());
+    }
+    if text.chars().count() > MAX_TEMPLATE_LENGTH {
+        problems.push(format!("Sentences can be at most {MAX_TEMPLATE_LENGTH} characters long."));
+    }
+
+    let (placeholders, agreements) = template_placeholders(text);
+    if text.matches('[').count() != placeholders.len() || text.matches(']').count() != placeholders.len() {
+        problems.push("Every `[` needs a matching `]`.".to_owned());
+    }
+    if text.matches('{').count() != agreements || text.matches('}').count() != agreements {
+        problems.push("`{singular|plural}` pieces have to be right next to a placeholder.".to_owned());
     }
 
-    let mut text: String = before.to_owned();
-    // Importantly, we don't compare filter whitespace. This allows us to still
-    // not have any before text as Discord will remove spaces at the start for us
-    if before.eq("") {
-        text = "Okay, how do these look?".to_owned();
+    let mut uses_name = false;
+    let mut uses_pronouns = false;
+    for placeholder in &placeholders {
+        if *placeholder == "name" {
+            uses_name = true;
+        } else if Form::from_name(placeholder).is_some() {
+            uses_pronouns = true;
+        } else {
+            problems.push(format!("`[{placeholder}]` isn't a placeholder, use `[name]` or a pronoun form like `[subjective]`."));
+        }
+    }
+
+    let sentence_type = match (uses_name, uses_pronouns) {
+        (true, true) => SentenceType::NamesPronouns,
+        (false, true) => SentenceType::PronounsOnly,
+        (true, false) => SentenceType::NamesOnly,
+        (false, false) => {
+            // Unknown placeholders have already been complained about
+            if placeholders.is_empty() {
+                problems.push("Sentences need at least one placeholder.".to_owned());
+            }
+            SentenceType::Invalid
+        }
+    };
+
+    match problems.is_empty() {
+        true => Ok(sentence_type),
+        false => Err(problems.join("\n"))
+    }
+}
+
+/// Tags are compared case-insensitively and can't have the separators used
+/// to store them
+pub fn normalise_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.contains([',', '\u{1f}']) {
+        return Err(format!("`{tag}` isn't a valid tag, tags can't be empty or have commas in them"));
+    }
+    Ok(tag)
+}
+
+/// Picks sentences by their tags. A sentence has to have at least one of the
+/// included tags (if there are any) and none of the excluded ones
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TagFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>
+}
+
+impl TagFilter {
+    pub fn matches(&self, sentence: &Sentence) -> bool {
+        let has = |tag: &String| sentence.tags.iter().any(|own| own.eq_ignore_ascii_case(tag));
+        (self.include.is_empty() || self.include.iter().any(has)) && !self.exclude.iter().any(has)
+    }
+}
+
+/// A filter given with the request wins, then the guild's default, then no
+/// filter at all. A guild default that can't be loaded counts as none
+pub async fn resolve_filter(store: &dyn SentenceStore, filter: Option<TagFilter>, guild: Option<&str>) -> TagFilter {
+    if let Some(filter) = filter {
+        return filter;
+    }
+    match guild {
+        Some(guild) => match store.guild_filter(guild).await {
+            Ok(filter) => filter.unwrap_or_default(),
+            Err(error) => {
+                println!("{}Couldn't load guild {guild}'s filter, using none: {error}", cs());
+                TagFilter::default()
+            }
+        },
+        None => TagFilter::default()
+    }
+}
+
+/// How many served sentences are remembered for each requester
+const HISTORY_LENGTH: usize = 50;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionMode {
+    /// Any sentences, preferring ones the requester hasn't seen
+    #[default]
+    Random,
+    /// Sentences that between them use every form of every set
+    Coverage
+}
+
+/// The most sentences one request can ask for
+pub const MAX_SENTENCES: usize = 10;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SentenceOptions {
+    pub filter: TagFilter,
+    /// Packs whose sentences can be used as well as the ones that aren't in
+    /// any pack, see `packs::resolve_packs`
+    pub packs: Vec<String>,
+    /// Any identifier that's stable for one user, it's only used to avoid
+    /// showing them the same sentences again
+    pub requester: Option<String>,
+    pub mode: SelectionMode,
+    pub count: usize,
+    /// The language wanted, falling back through `locale::fallback_chain`
+    /// to the first one that has any sentences
+    pub locale: Option<String>,
+    /// Text before and after the sentences. These go through `genderify_text`
+    /// too, so they can use placeholders. Leaving out the intro uses the
+    /// default one for the language, an empty intro means no intro at all
+    pub intro: Option<String>,
+    pub outro: String
+}
+
+impl Default for SentenceOptions {
+    fn default() -> Self {
+        SentenceOptions {
+            filter: TagFilter::default(),
+            packs: Vec::new(),
+            requester: None,
+            mode: SelectionMode::Random,
+            count: 3,
+            locale: None,
+            intro: None,
+            outro: "".to_owned()
+        }
+    }
+}
+
+/// Sentences ready to be shown, see `formatters` for turning them into text
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeneratedSentences {
+    /// The language the sentences ended up in
+    pub language: String,
+    pub intro: String,
+    pub sentences: Vec<String>,
+    pub outro: String
+}
+
+/// Puts sentences in the order they should be picked: unseen ones first in a
+/// random order, then seen ones by how long ago they were seen. `history` is
+/// most recent first
+fn order_by_history(pool: &mut [CompiledSentence], history: &[u64]) {
+    let mut rng = rand::thread_rng();
+    pool.shuffle(&mut rng);
+    pool.sort_by_key(|compiled| match history.iter().position(|id| *id == compiled.sentence.id) {
+        Some(position) => history.len() - position,
+        None => 0
+    });
+}
+
+/// Greedily picks whichever sentence and set cover the most forms that
+/// haven't been used yet. Each pick comes with the set it should be shown
+/// with, or `None` once there's nothing left to cover
+fn pick_covering(mut candidates: Vec<CompiledSentence>, set_count: usize, count: usize) -> Vec<(CompiledSentence, Option<usize>)> {
+    let mut uncovered: Vec<(usize, Form)> = (0..set_count)
+        .flat_map(|set| Form::ALL.into_iter().map(move |form| (set, form)))
+        .collect();
+
+    let mut picked = Vec::new();
+    while picked.len() < count && !candidates.is_empty() {
+        let mut best: Option<(usize, usize, usize)> = None;
+        for (index, candidate) in candidates.iter().enumerate() {
+            for set in 0..set_count {
+                let gain = candidate.template.forms.iter().filter(|form| uncovered.contains(&(set, **form))).count();
+                // Strictly greater so earlier (preferred) candidates win ties
+                if gain > 0 && best.is_none_or(|(_, _, best_gain)| gain > best_gain) {
+                    best = Some((index, set, gain));
+                }
+            }
+        }
+        match best {
+            Some((index, set, _)) => {
+                let candidate = candidates.remove(index);
+                uncovered.retain(|(uncovered_set, form)| *uncovered_set != set || !candidate.template.forms.contains(form));
+                picked.push((candidate, Some(set)));
+            }
+            // Everything's covered (or can't be), so go back to the usual order
+            None => picked.push((candidates.remove(0), None))
+        }
+    }
+    picked
+}
+
+// Ahhh, good old `generate_sentences`, like the return of an old friend
+pub async fn generate_sentences(names: Vec<String>, sets: Vec<PronounSet>, store: &dyn SentenceStore, options: &SentenceOptions) -> Result<GeneratedSentences, DispatchError> {
+    if sets.is_empty() && names.is_empty() {
+        return Err(DispatchError::Invalid("Can't make sentences with no names or pronouns :(".to_owned()));
+    }
+    if options.count == 0 || options.count > MAX_SENTENCES {
+        return Err(DispatchError::Invalid(format!("You can ask for between 1 and {MAX_SENTENCES} sentences.")));
     }
 
     let sentence_type = match sets.len() {
-        0 => 2,
+        0 => SentenceType::NamesOnly,
         _ => match names.len() {
-            0 => 1,
-            _ => 0
+            0 => SentenceType::PronounsOnly,
+            _ => SentenceType::NamesPronouns
         }
     };
-    
-    // It's an integer so we don't need to sanitise it
-    let mut raw_sentences: Vec<String> = match db.query("SELECT Sentence FROM Sentences WHERE Type=".to_owned() + &sentence_type.to_string()).await {
-        Ok(result) => result,
-        Err(error) => return Err(error.to_string())
+
+    let mut raw_sentences = store.fetch_compiled(sentence_type).await.map_err(DispatchError::Unavailable)?;
+    raw_sentences.retain(|CompiledSentence { sentence, .. }| {
+        let enabled = sentence.pack.as_ref().is_none_or(|pack| options.packs.contains(pack));
+        enabled && options.filter.matches(sentence)
+    });
+    let chain = fallback_chain(options.locale.as_deref().unwrap_or(DEFAULT_LANGUAGE));
+    let language = match chain.into_iter().find(|language| raw_sentences.iter().any(|compiled| compiled.sentence.language.eq_ignore_ascii_case(language))) {
+        Some(language) => language,
+        None => return Err(DispatchError::Invalid("There aren't any sentences to show for that yet.".to_owned()))
     };
+    raw_sentences.retain(|compiled| compiled.sentence.language.eq_ignore_ascii_case(&language));
 
-    let mut rng = rand::thread_rng();
-    for i in 1..4 {
-        let index = rng.gen_range(0..raw_sentences.len());
-        text += &format!("\n\n**Sentence {}**\n", i).to_owned();
-        text += raw_sentences.swap_remove(index).as_str();
+    let requester = options.requester.as_deref();
+    // History only changes the order, so sentences can still be made without it
+    let history = match requester {
+        Some(requester) => store.history(requester, HISTORY_LENGTH).await.unwrap_or_else(|error| {
+            println!("{}Couldn't load {requester}'s history, ignoring it: {error}", cs());
+            Vec::new()
+        }),
+        None => Vec::new()
+    };
+    order_by_history(&mut raw_sentences, &history);
+    let picked: Vec<(CompiledSentence, Option<usize>)> = match options.mode {
+        SelectionMode::Random => raw_sentences.into_iter().take(options.count).map(|sentence| (sentence, None)).collect(),
+        SelectionMode::Coverage => pick_covering(raw_sentences, sets.len(), options.count)
+    };
+
+    let sentences = picked.iter().map(|(compiled, set)| {
+        // Sentences picked to cover a set have to be shown with that set
+        let sentence_sets = match set {
+            Some(set) => vec![sets[*set].clone()],
+            None => sets.clone()
+        };
+        compiled.template.genderify(names.clone(), sentence_sets)
+    }).collect();
+
+    if let Some(requester) = requester {
+        let ids: Vec<u64> = picked.iter().map(|(compiled, _)| compiled.sentence.id).collect();
+        if let Err(error) = store.record_history(requester, &ids, HISTORY_LENGTH).await {
+            println!("{}Couldn't save {requester}'s history: {error}", cs());
+        }
     }
-    text += after;
 
-    Ok(genderify_text(text.as_str(), names, sets))
-}
\ No newline at end of file
+    let intro = options.intro.as_deref().unwrap_or_else(|| default_intro(&language));
+    Ok(GeneratedSentences {
+        language,
+        intro: genderify_text(intro, names.clone(), sets.clone()),
+        sentences,
+        outro: genderify_text(&options.outro, names, sets)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiled(text: &str) -> CompiledSentence {
+        CompiledSentence::new(Sentence {
+            id: 0,
+            sentence_type: SentenceType::PronounsOnly,
+            text: text.to_owned(),
+            tags: vec![],
+            pack: None,
+            language: "en".to_owned(),
+            submitter: None
+        })
+    }
+
+    #[test]
+    fn covering_uses_every_form_of_every_set() {
+        let every_form = "[subjective] told [objective] that [possessive] cat was [possessive2] and [reflexive] alone.";
+        let candidates = vec![
+            compiled("[subjective] waved."),
+            compiled(every_form),
+            compiled(&format!("Again, {every_form}")),
+            compiled("[subjective] left.")
+        ];
+        let picked = pick_covering(candidates, 2, 4);
+
+        // The two sentences with every form cover both sets between them
+        assert_eq!(picked[0].0.sentence.text, every_form);
+        assert_eq!(picked[0].1, Some(0));
+        assert_eq!(picked[1].0.sentence.text, format!("Again, {every_form}"));
+        assert_eq!(picked[1].1, Some(1));
+        let covered: Vec<(usize, Form)> = picked.iter()
+            .filter_map(|(candidate, set)| set.map(|set| candidate.template.forms.iter().map(move |form| (set, *form))))
+            .flatten()
+            .collect();
+        for set in 0..2 {
+            for form in Form::ALL {
+                assert!(covered.contains(&(set, form)));
+            }
+        }
+
+        // Then the rest come in their usual order
+        assert_eq!(picked[2].0.sentence.text, "[subjective] waved.");
+        assert_eq!(picked[2].1, None);
+        assert_eq!(picked[3].0.sentence.text, "[subjective] left.");
+        assert_eq!(picked[3].1, None);
+    }
+}
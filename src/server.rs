@@ -0,0 +1,379 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{self, AsyncBufRead, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{Semaphore, mpsc};
+use tokio::time::timeout;
+
+use crate::engine::InferenceRules;
+use crate::framing::{Frame, MAX_FRAME_SIZE, read_frame, write_frame};
+use crate::protocol::{Command, DispatchError, Envelope, PROTOCOL_VERSION, Request, Response, dispatch, hello};
+use crate::shared::console_stamp as cs;
+use crate::storage::SentenceStore;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Clients past this are told the engine is busy and disconnected
+    pub max_connections: usize,
+    /// Requests being worked on at once across every connection, the rest wait
+    pub max_in_flight: usize,
+    /// Requests one connection can have waiting on replies, it isn't read
+    /// from again until one finishes
+    pub max_pipelined: usize,
+    /// Connections that don't send anything for this long are closed
+    pub idle_timeout_seconds: u64,
+    /// Connections that don't read a reply, or leave a request waiting for
+    /// room in their pipeline, for this long are closed
+    pub write_timeout_seconds: u64,
+    /// Print every request and reply in full, testing mode turns this on
+    pub log_requests: bool
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            max_connections: 64,
+            max_in_flight: 16,
+            max_pipelined: 32,
+            idle_timeout_seconds: 300,
+            write_timeout_seconds: 30,
+            log_requests: false
+        }
+    }
+}
+
+/// Everything a connection needs, cheap to clone into each one's task
+#[derive(Clone)]
+pub struct Server {
+    store: Arc<dyn SentenceStore>,
+    rules: Arc<InferenceRules>,
+    connections: Arc<Semaphore>,
+    in_flight: Arc<Semaphore>,
+    max_pipelined: usize,
+    idle_timeout: Duration,
+    write_timeout: Duration,
+    log_requests: bool
+}
+
+impl Server {
+    pub fn new(store: Arc<dyn SentenceStore>, rules: InferenceRules, config: &ServerConfig) -> Server {
+        Server {
+            store,
+            rules: Arc::new(rules),
+            connections: Arc::new(Semaphore::new(config.max_connections)),
+            in_flight: Arc::new(Semaphore::new(config.max_in_flight)),
+            // A channel can't have no room at all
+            max_pipelined: config.max_pipelined.max(1),
+            idle_timeout: Duration::from_secs(config.idle_timeout_seconds),
+            write_timeout: Duration::from_secs(config.write_timeout_seconds),
+            log_requests: config.log_requests
+        }
+    }
+
+    /// Serves every connection on its own task, forever
+    pub async fn run(self, listener: UnixListener) {
+        println!("{}Accepting connections now!", cs());
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _addr)) => stream,
+                Err(error) => {
+                    println!("{}Connection error: {error}", cs());
+                    continue;
+                }
+            };
+            let permit = match self.connections.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    println!("{}Turned a client away, there are too many connected", cs());
+                    tokio::spawn(reject(stream));
+                    continue;
+                }
+            };
+            println!("{}New client!", cs());
+            let server = self.clone();
+            tokio::spawn(async move {
+                server.handle_client(stream).await;
+                drop(permit);
+            });
+        }
+    }
+
+    /// Reads requests as they come and answers each one as soon as it's
+    /// done, so replies can come back in a different order
+    async fn handle_client(&self, stream: UnixStream) {
+        let (reader, mut writer) = io::split(stream);
+        let mut reader = BufReader::new(reader);
+
+        let (replies, mut outgoing) = mpsc::channel::<Envelope>(self.max_pipelined);
+        let write_timeout = self.write_timeout;
+        // Giving up drops `outgoing`, which tells the reading side to stop too
+        let writing = tokio::spawn(async move {
+            while let Some(envelope) = outgoing.recv().await {
+                match timeout(write_timeout, write_frame(&mut writer, &envelope)).await {
+                    Ok(Ok(())) => (),
+                    Ok(Err(error)) => {
+                        println!("{}Error sending reply: {error}", cs());
+                        return;
+                    }
+                    Err(_) => {
+                        println!("{}Closed a connection that wasn't reading its replies", cs());
+                        return;
+                    }
+                }
+            }
+        });
+        // Stops reading once this many requests are waiting on a reply
+        let pipeline = Arc::new(Semaphore::new(self.max_pipelined));
+
+        if !self.greet(&mut reader, &replies).await {
+            // There's only the handshake's reply to send
+            drop(replies);
+            let _ = writing.await;
+            return;
+        }
+        loop {
+            let read = tokio::select! {
+                read = timeout(self.idle_timeout, read_frame(&mut reader, MAX_FRAME_SIZE)) => read,
+                // Replies can't be sent any more, so there's no point reading
+                _ = replies.closed() => break
+            };
+            let frame = match read {
+                Ok(Ok(Some(frame))) => frame,
+                // They've hung up
+                Ok(Ok(None)) => break,
+                Ok(Err(error)) => {
+                    println!("{}Error reading request: {error}", cs());
+                    break;
+                }
+                Err(_) => {
+                    println!("{}Closed an idle connection", cs());
+                    break;
+                }
+            };
+            // Start your timer!
+            let now = Instant::now();
+
+            let permit = match timeout(self.write_timeout, pipeline.clone().acquire_owned()).await {
+                Ok(Ok(permit)) => permit,
+                Ok(Err(_)) => break,
+                Err(_) => {
+                    println!("{}Closed a connection with too many requests waiting", cs());
+                    break;
+                }
+            };
+            let server = self.clone();
+            let replies = replies.clone();
+            tokio::spawn(async move {
+                let envelope = server.respond(frame, now).await;
+                // The writer only stops if the client's gone, so there's no one to tell
+                let _ = replies.send(envelope).await;
+                drop(permit);
+            });
+        }
+
+        // Let anything still running finish and send its reply
+        drop(replies);
+        let _ = writing.await;
+    }
+
+    /// Reads the handshake, which has to come before anything else. `false`
+    /// means the client can't be served and the connection should close
+    async fn greet<R: AsyncBufRead + Unpin>(&self, reader: &mut R, replies: &mpsc::Sender<Envelope>) -> bool {
+        let frame = match timeout(self.idle_timeout, read_frame(reader, MAX_FRAME_SIZE)).await {
+            Ok(Ok(Some(frame))) => frame,
+            _ => return false
+        };
+        let now = Instant::now();
+
+        let (id, result) = match frame {
+            Frame::Message(data) => match Request::parse(&data) {
+                (id, Ok(Request { command: Command::Hello { version, features }, .. })) => (id, hello(version, &features)),
+                (id, Ok(_)) => (id, Err(format!("Send a `hello` with the protocol version first, this engine speaks version {PROTOCOL_VERSION}"))),
+                (id, Err(error)) => (id, Err(error))
+            },
+            Frame::TooLong => (None, Err(format!("Requests can be at most {MAX_FRAME_SIZE} bytes")))
+        };
+        let greeted = result.is_ok();
+        if let Err(error) = &result {
+            println!("{}Turned a client away: {error}", cs());
+        }
+        let _ = replies.send(Envelope::new(id, result, now)).await;
+        greeted
+    }
+
+    async fn respond(&self, frame: Frame, now: Instant) -> Envelope {
+        if let (true, Frame::Message(data)) = (self.log_requests, &frame) {
+            println!("{}Request: {}", cs(), String::from_utf8_lossy(data));
+        }
+        let (id, result) = match frame {
+            Frame::Message(data) => match Request::parse(&data) {
+                (id, Ok(request)) => (id, self.dispatch(request.command).await.map_err(|error| error.to_string())),
+                (id, Err(error)) => (id, Err(error))
+            },
+            Frame::TooLong => (None, Err(format!("Requests can be at most {MAX_FRAME_SIZE} bytes")))
+        };
+        match &result {
+            // How long did it take?
+            Ok(_) => println!("{}Processed request: {}ms", cs(), now.elapsed().as_millis()),
+            Err(error) => println!("{}Error at {}ms: {error}", cs(), now.elapsed().as_millis())
+        }
+        let envelope = Envelope::new(id, result, now);
+        if self.log_requests {
+            println!("{}Reply: {}", cs(), serde_json::to_string(&envelope).unwrap_or_default());
+        }
+        envelope
+    }
+
+    pub fn log_requests(&self) -> bool {
+        self.log_requests
+    }
+
+    /// Waits for a free slot first, so a burst of requests can't swamp storage
+    pub async fn dispatch(&self, command: Command) -> Result<Response, DispatchError> {
+        let _permit = self.in_flight.acquire().await.map_err(|error| DispatchError::Internal(error.to_string()))?;
+        dispatch(command, self.store.as_ref(), &self.rules).await
+    }
+}
+
+async fn reject(stream: UnixStream) {
+    let (_, mut writer) = io::split(stream);
+    let envelope = Envelope::new(None, Err("The engine is busy, try again soon".to_owned()), Instant::now());
+    let _ = write_frame(&mut writer, &envelope).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use serde_json::{Value, json};
+    use tokio::io::{ReadHalf, WriteHalf};
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::packs::PackManifest;
+    use crate::protocol::{RequestId, Status};
+    use crate::sentences::{SentenceType, TagFilter};
+    use crate::storage::Sentence;
+    use crate::storage::memory::MemoryStore;
+    use crate::submissions::{Submission, SubmissionStatus};
+
+    /// Refreshing waits until the test says it can finish
+    struct Held {
+        inner: MemoryStore,
+        release: Arc<Notify>
+    }
+
+    #[async_trait]
+    impl SentenceStore for Held {
+        async fn fetch(&self, sentence_type: SentenceType) -> Result<Vec<Sentence>, String> { self.inner.fetch(sentence_type).await }
+        async fn add(&self, sentence: &Sentence) -> Result<u64, String> { self.inner.add(sentence).await }
+        async fn remove(&self, id: u64) -> Result<bool, String> { self.inner.remove(id).await }
+        async fn list(&self) -> Result<Vec<Sentence>, String> { self.inner.list().await }
+        async fn set_tags(&self, id: u64, tags: &[String]) -> Result<bool, String> { self.inner.set_tags(id, tags).await }
+        async fn refresh(&self) -> Result<(), String> {
+            self.release.notified().await;
+            Ok(())
+        }
+        async fn history(&self, requester: &str, limit: usize) -> Result<Vec<u64>, String> { self.inner.history(requester, limit).await }
+        async fn record_history(&self, requester: &str, ids: &[u64], keep: usize) -> Result<(), String> { self.inner.record_history(requester, ids, keep).await }
+        async fn add_submission(&self, submission: &Submission) -> Result<u64, String> { self.inner.add_submission(submission).await }
+        async fn update_submission(&self, submission: &Submission, from: SubmissionStatus) -> Result<bool, String> { self.inner.update_submission(submission, from).await }
+        async fn submission(&self, id: u64) -> Result<Option<Submission>, String> { self.inner.submission(id).await }
+        async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String> { self.inner.submissions(status).await }
+        async fn packs(&self) -> Result<Vec<PackManifest>, String> { self.inner.packs().await }
+        async fn save_pack(&self, manifest: &PackManifest) -> Result<(), String> { self.inner.save_pack(manifest).await }
+        async fn remove_pack(&self, name: &str) -> Result<bool, String> { self.inner.remove_pack(name).await }
+        async fn guild_filter(&self, guild: &str) -> Result<Option<TagFilter>, String> { self.inner.guild_filter(guild).await }
+        async fn set_guild_filter(&self, guild: &str, filter: Option<&TagFilter>) -> Result<(), String> { self.inner.set_guild_filter(guild, filter).await }
+        async fn guild_packs(&self, guild: &str) -> Result<Option<Vec<String>>, String> { self.inner.guild_packs(guild).await }
+        async fn set_guild_packs(&self, guild: &str, packs: Option<&[String]>) -> Result<(), String> { self.inner.set_guild_packs(guild, packs).await }
+    }
+
+    struct Client {
+        reader: BufReader<ReadHalf<UnixStream>>,
+        writer: WriteHalf<UnixStream>
+    }
+
+    impl Client {
+        async fn send(&mut self, request: Value) {
+            write_frame(&mut self.writer, &request).await.unwrap();
+        }
+
+        /// `None` once the engine has hung up
+        async fn receive(&mut self) -> Option<Envelope> {
+            match read_frame(&mut self.reader, MAX_FRAME_SIZE).await.unwrap()? {
+                Frame::Message(data) => Some(serde_json::from_slice(&data).unwrap()),
+                Frame::TooLong => panic!("The reply was too long")
+            }
+        }
+    }
+
+    /// A server on one end of a socket pair, with the release for `Held`
+    fn connect() -> (Client, Arc<Notify>) {
+        let release = Arc::new(Notify::new());
+        let store = Held { inner: MemoryStore::new(), release: release.clone() };
+        let server = Server::new(Arc::new(store), InferenceRules::default(), &ServerConfig::default());
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        tokio::spawn(async move { server.handle_client(theirs).await });
+        let (reader, writer) = io::split(ours);
+        (Client { reader: BufReader::new(reader), writer }, release)
+    }
+
+    #[tokio::test]
+    async fn replies_come_back_as_they_finish() {
+        let (mut client, release) = connect();
+        client.send(json!({"request_id": 1, "name": "hello", "version": PROTOCOL_VERSION})).await;
+        assert_eq!(client.receive().await.unwrap().status, Status::Ok);
+
+        client.send(json!({"request_id": "slow", "name": "refresh_sentences"})).await;
+        client.send(json!({"request_id": 2, "name": "parse", "raw": "she/her"})).await;
+        let fast = client.receive().await.unwrap();
+        assert_eq!(fast.request_id, Some(RequestId::Number(2)));
+        assert_eq!(fast.status, Status::Ok);
+
+        release.notify_one();
+        let slow = client.receive().await.unwrap();
+        assert_eq!(slow.request_id, Some(RequestId::Text("slow".to_owned())));
+        assert_eq!(slow.status, Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn request_ids_come_back_on_errors() {
+        let (mut client, _) = connect();
+        client.send(json!({"request_id": 1, "name": "hello", "version": PROTOCOL_VERSION})).await;
+        assert_eq!(client.receive().await.unwrap().request_id, Some(RequestId::Number(1)));
+
+        client.send(json!({"request_id": "typo", "name": "genderfy"})).await;
+        let reply = client.receive().await.unwrap();
+        assert_eq!(reply.request_id, Some(RequestId::Text("typo".to_owned())));
+        assert_eq!(reply.status, Status::Error);
+
+        client.send(json!({"request_id": 3, "name": "parse"})).await;
+        let reply = client.receive().await.unwrap();
+        assert_eq!(reply.request_id, Some(RequestId::Number(3)));
+        assert_eq!(reply.status, Status::Error);
+    }
+
+    #[tokio::test]
+    async fn unsupported_versions_are_turned_away() {
+        let (mut client, _) = connect();
+        client.send(json!({"request_id": 1, "name": "hello", "version": PROTOCOL_VERSION + 1})).await;
+        let reply = client.receive().await.unwrap();
+        assert_eq!(reply.request_id, Some(RequestId::Number(1)));
+        assert_eq!(reply.status, Status::Error);
+        assert!(client.receive().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn hello_has_to_come_first() {
+        let (mut client, _) = connect();
+        client.send(json!({"request_id": 1, "name": "parse", "raw": "she/her"})).await;
+        let reply = client.receive().await.unwrap();
+        assert_eq!(reply.request_id, Some(RequestId::Number(1)));
+        assert_eq!(reply.status, Status::Error);
+        assert!(reply.error.unwrap().contains("hello"));
+        assert!(client.receive().await.is_none());
+    }
+}
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{Form, PronounSet, parse_set};
+
+// Tokens start with this so they can't be mistaken for a set, and so the
+// format can change later without breaking old links
+const TOKEN_PREFIX: &str = "pe1.";
+const BASE64_URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// A set along with the name it was shared with, if any
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SharedSet {
+    pub set: PronounSet,
+    pub name: Option<String>
+}
+
+// What actually goes in a token: the five forms, plurality, then the name
+#[derive(Serialize, Deserialize)]
+struct TokenPayload(String, String, String, String, String, bool, Option<String>);
+
+impl PronounSet {
+    /// The URL-path form of this set, e.g. "ze/hir/hir/hirs/hirself". It's
+    /// written the same way as `short`, just escaped
+    pub fn to_path(&self) -> String {
+        self.short().split('/').map(percent_encode).collect::<Vec<String>>().join("/")
+    }
+
+    /// Reads a URL or URL path like "https://pronoun.is/she/or/they" into
+    /// every set it lists. The scheme can be left off
+    pub fn from_path(path: &str) -> Result<Vec<PronounSet>, &'static str> {
+        let mut path = path.trim();
+        // Drop the scheme and host, we only care about the path
+        let scheme = path.find("://");
+        if let Some(index) = scheme {
+            path = &path[index + 3..];
+        }
+        if scheme.is_some() || path.split('/').next().is_some_and(is_host) {
+            path = path.find('/').map_or("", |index| &path[index..]);
+        }
+        // Query strings and fragments aren't part of the set
+        if let Some(index) = path.find(['?', '#']) {
+            path = &path[..index];
+        }
+
+        let decoded = percent_decode(path.trim_matches('/'))?;
+        let mut sets = Vec::new();
+        for raw in decoded.split("/or/") {
+            sets.push(parse_set(raw)?);
+        }
+        Ok(sets)
+    }
+
+    /// A compact, URL-safe token that keeps plurality and the name along
+    /// with the set itself
+    pub fn to_token(&self, name: Option<&str>) -> String {
+        let payload = TokenPayload(
+            self.form(Form::Subjective).to_owned(),
+            self.form(Form::Objective).to_owned(),
+            self.form(Form::Possessive).to_owned(),
+            self.form(Form::Possessive2).to_owned(),
+            self.form(Form::Reflexive).to_owned(),
+            self.plural(),
+            name.map(|name| name.to_owned())
+        );
+        // This can't fail, it's all strings and bools
+        let json = serde_json::to_string(&payload).unwrap_or_default();
+        TOKEN_PREFIX.to_owned() + &base64_url_encode(json.as_bytes())
+    }
+
+    pub fn from_token(token: &str) -> Result<SharedSet, &'static str> {
+        const INVALID: &str = "That share token isn't valid.";
+
+        let encoded = token.trim().strip_prefix(TOKEN_PREFIX).ok_or(INVALID)?;
+        let json = base64_url_decode(encoded).ok_or(INVALID)?;
+        let payload: TokenPayload = serde_json::from_slice(&json).map_err(|_| INVALID)?;
+        let TokenPayload(subjective, objective, possessive, possessive2, reflexive, plural, name) = payload;
+
+        let suffix = if plural { ":p" } else { ":s" };
+        let set = parse_set(&([subjective, objective, possessive, possessive2, reflexive].join("/") + suffix))?;
+        Ok(SharedSet { set, name })
+    }
+}
+
+/// Accepts anything a user might paste: a share token, a link, or a set
+/// written out by hand
+pub fn import(raw: &str) -> Result<SharedSet, &'static str> {
+    let raw = raw.trim();
+    if raw.starts_with(TOKEN_PREFIX) {
+        return PronounSet::from_token(raw);
+    }
+    if raw.contains("://") || raw.contains("/or/") || raw.split('/').next().is_some_and(is_host) {
+        let mut sets = PronounSet::from_path(raw)?;
+        return match sets.len() {
+            1 => Ok(SharedSet { set: sets.remove(0), name: None }),
+            _ => Err("That link has more than one set in it, share them one at a time.")
+        };
+    }
+    Ok(SharedSet { set: parse_set(raw)?, name: None })
+}
+
+// Whether the start of a link without its scheme is a host like `pronoun.is`,
+// no pronoun has a dot between letters
+fn is_host(segment: &str) -> bool {
+    let labels: Vec<&str> = segment.split('.').collect();
+    labels.len() > 1 && labels.iter().all(|label| !label.is_empty() && label.chars().all(|char| char.is_ascii_alphanumeric() || char == '-'))
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'\'' | b':' => encoded.push(byte as char),
+            _ => encoded += &format!("%{byte:02X}")
+        }
+    }
+    encoded
+}
+
+// Only `%` escapes, a `+` in a path is a plus rather than a space like it
+// would be in a form
+fn percent_decode(text: &str) -> Result<String, &'static str> {
+    const INVALID: &str = "That link has an invalid escape in it.";
+
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = text.get(i + 1..i + 3).ok_or(INVALID)?;
+                decoded.push(u8::from_str_radix(hex, 16).map_err(|_| INVALID)?);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| INVALID)
+}
+
+fn base64_url_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 4 / 3 + 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        // Unpadded, so a chunk of n bytes gives n + 1 characters
+        for i in 0..=chunk.len() {
+            encoded.push(BASE64_URL[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    encoded
+}
+
+fn base64_url_decode(text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.as_bytes().chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut n = 0u32;
+        for (i, char) in chunk.iter().enumerate() {
+            let value = BASE64_URL.iter().position(|c| c == char)? as u32;
+            n |= value << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            decoded.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_round_trip() {
+        for (raw, name) in [("she/her", None), ("xe/xem/xyr:p", Some("Ada")), ("🌟/🌟self", Some("Ünïcode \"name\""))] {
+            let set = parse_set(raw).unwrap();
+            let token = set.to_token(name);
+            assert!(token.starts_with(TOKEN_PREFIX));
+            assert!(token.bytes().all(|byte| byte.is_ascii_alphanumeric() || b".-_".contains(&byte)), "{token}");
+            let shared = PronounSet::from_token(&token).unwrap();
+            assert_eq!(shared.set, set);
+            assert_eq!(shared.name.as_deref(), name);
+        }
+    }
+
+    #[test]
+    fn broken_tokens() {
+        for token in ["pe1.", "pe1.!!!!", "pe1.A", "she/her", "pe2.eyJ9"] {
+            assert!(PronounSet::from_token(token).is_err(), "{token}");
+        }
+    }
+
+    #[test]
+    fn paths_round_trip() {
+        for raw in ["she/her", "they/them", "xe/xem/xyr", "ze/hir/hir/hirs/hirself", "star/starself", "x+y/x+y/x+y's/x+y's/x+yself", "xe/xem/xyr:p"] {
+            let set = parse_set(raw).unwrap();
+            assert_eq!(PronounSet::from_path(&set.to_path()).unwrap(), vec![set], "{raw}");
+        }
+        assert_eq!(parse_set("she/her").unwrap().to_path(), "she/her");
+        assert_eq!(parse_set("xe/xem/xyr/xyrs/xemself").unwrap().to_path(), "xe/xem/xyr");
+        assert_eq!(parse_set("they/them:s").unwrap().to_path(), "they/them:s");
+    }
+
+    #[test]
+    fn paths_and_short_agree() {
+        for raw in ["he/him", "it/its", "star/starself", "ze/hir/hir/hirs/hirself", "ey/em/eir:p", "she:p"] {
+            let set = parse_set(raw).unwrap();
+            assert_eq!(set.to_path(), set.short(), "{raw}");
+            assert_eq!(parse_set(&set.short()).unwrap(), set, "{raw}");
+        }
+    }
+
+    #[test]
+    fn links_without_a_scheme() {
+        let shared = import("pronoun.is/xe/xem/xyr").unwrap();
+        assert_eq!(shared.set, parse_set("xe/xem/xyr").unwrap());
+        assert_eq!(import("https://pronoun.is/they/them:s").unwrap().set, parse_set("they:s").unwrap());
+        assert_eq!(PronounSet::from_path("pronoun.is/she/or/they").unwrap().len(), 2);
+        // A set written by hand is still just a set
+        assert_eq!(import("ze/hir/hir").unwrap().set, parse_set("ze/hir/hir").unwrap());
+    }
+
+    #[test]
+    fn links_with_more_than_one_set() {
+        for raw in ["pronoun.is/she/or/they", "https://pronoun.is/she/or/they", "she/or/they"] {
+            assert!(import(raw).is_err(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn plus_stays_a_plus() {
+        let sets = PronounSet::from_path("https://example.com/x+y/x+y/x+y's/x+y's/x+yself?ref=1").unwrap();
+        assert_eq!(sets[0].form(Form::Subjective), "x+y");
+        let sets = PronounSet::from_path("/she/or/they%2Fthem").unwrap();
+        assert_eq!(sets.len(), 2);
+    }
+}
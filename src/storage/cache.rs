@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::packs::PackManifest;
+use crate::sentences::{SentenceType, TagFilter};
+use crate::shared::console_stamp as cs;
+use crate::storage::{CompiledSentence, Sentence, SentenceStore};
+use crate::submissions::{Submission, SubmissionStatus};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// How often sentences are reloaded from storage, 0 means only when
+    /// something changes or a refresh is asked for
+    pub refresh_seconds: u64
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: true,
+            refresh_seconds: 300
+        }
+    }
+}
+
+/// Every sentence as of the last successful load, already split up by type
+/// and with their templates parsed
+struct Snapshot {
+    by_type: HashMap<SentenceType, Vec<CompiledSentence>>,
+    all: Vec<Sentence>,
+    loaded: Instant
+}
+
+impl Snapshot {
+    fn new(all: Vec<Sentence>) -> Snapshot {
+        let mut by_type: HashMap<SentenceType, Vec<CompiledSentence>> = HashMap::new();
+        for sentence in &all {
+            by_type.entry(sentence.sentence_type).or_default().push(CompiledSentence::new(sentence.clone()));
+        }
+        Snapshot { by_type, all, loaded: Instant::now() }
+    }
+}
+
+/// Serves sentences from memory, everything else goes straight through to
+/// the store underneath. If a reload fails the last good snapshot is kept,
+/// so requests keep working while the database is down
+#[derive(Clone)]
+pub struct CachedStore {
+    inner: Arc<dyn SentenceStore>,
+    snapshot: Arc<RwLock<Snapshot>>
+}
+
+impl CachedStore {
+    /// Fails if the first load does, there'd be nothing to serve
+    pub async fn new(inner: Arc<dyn SentenceStore>) -> Result<CachedStore, String> {
+        let snapshot = Snapshot::new(inner.list().await?);
+        Ok(CachedStore { inner, snapshot: Arc::new(RwLock::new(snapshot)) })
+    }
+
+    /// Reloads on an interval for as long as the program runs
+    pub fn spawn_refresh(&self, config: &CacheConfig) {
+        if config.refresh_seconds == 0 {
+            return;
+        }
+        let cache = self.clone();
+        let period = Duration::from_secs(config.refresh_seconds);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick is immediate and we've only just loaded
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(error) = cache.refresh().await {
+                    println!("{}Couldn't refresh sentences: {error}", cs());
+                }
+            }
+        });
+    }
+
+    /// Reloads after a change, a failure only means the cache is a little
+    /// behind so it isn't passed on
+    async fn reload_after_change(&self) {
+        if let Err(error) = self.refresh().await {
+            println!("{}Couldn't refresh sentences after a change: {error}", cs());
+        }
+    }
+}
+
+#[async_trait]
+impl SentenceStore for CachedStore {
+    async fn fetch(&self, sentence_type: SentenceType) -> Result<Vec<Sentence>, String> {
+        let snapshot = self.snapshot.read().await;
+        Ok(snapshot.by_type.get(&sentence_type).map_or(Vec::new(), |sentences| {
+            sentences.iter().map(|compiled| compiled.sentence.clone()).collect()
+        }))
+    }
+
+    async fn fetch_compiled(&self, sentence_type: SentenceType) -> Result<Vec<CompiledSentence>, String> {
+        let snapshot = self.snapshot.read().await;
+        Ok(snapshot.by_type.get(&sentence_type).cloned().unwrap_or_default())
+    }
+
+    async fn add(&self, sentence: &Sentence) -> Result<u64, String> {
+        let id = self.inner.add(sentence).await?;
+        self.reload_after_change().await;
+        Ok(id)
+    }
+
+    async fn remove(&self, id: u64) -> Result<bool, String> {
+        let removed = self.inner.remove(id).await?;
+        self.reload_after_change().await;
+        Ok(removed)
+    }
+
+    async fn list(&self) -> Result<Vec<Sentence>, String> {
+        Ok(self.snapshot.read().await.all.clone())
+    }
+
+    async fn set_tags(&self, id: u64, tags: &[String]) -> Result<bool, String> {
+        let exists = self.inner.set_tags(id, tags).await?;
+        self.reload_after_change().await;
+        Ok(exists)
+    }
+
+    async fn refresh(&self) -> Result<(), String> {
+        match self.inner.list().await {
+            Ok(sentences) => {
+                *self.snapshot.write().await = Snapshot::new(sentences);
+                Ok(())
+            }
+            Err(error) => {
+                let age = self.snapshot.read().await.loaded.elapsed().as_secs();
+                Err(format!("{error} (still using sentences from {age}s ago)"))
+            }
+        }
+    }
+
+    async fn history(&self, requester: &str, limit: usize) -> Result<Vec<u64>, String> {
+        self.inner.history(requester, limit).await
+    }
+
+    async fn record_history(&self, requester: &str, ids: &[u64], keep: usize) -> Result<(), String> {
+        self.inner.record_history(requester, ids, keep).await
+    }
+
+    async fn add_submission(&self, submission: &Submission) -> Result<u64, String> {
+        self.inner.add_submission(submission).await
+    }
+
+    async fn update_submission(&self, submission: &Submission, from: SubmissionStatus) -> Result<bool, String> {
+        self.inner.update_submission(submission, from).await
+    }
+
+    async fn submission(&self, id: u64) -> Result<Option<Submission>, String> {
+        self.inner.submission(id).await
+    }
+
+    async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String> {
+        self.inner.submissions(status).await
+    }
+
+    async fn packs(&self) -> Result<Vec<PackManifest>, String> {
+        self.inner.packs().await
+    }
+
+    async fn save_pack(&self, manifest: &PackManifest) -> Result<(), String> {
+        self.inner.save_pack(manifest).await
+    }
+
+    async fn remove_pack(&self, name: &str) -> Result<bool, String> {
+        let removed = self.inner.remove_pack(name).await?;
+        self.reload_after_change().await;
+        Ok(removed)
+    }
+
+    async fn guild_filter(&self, guild: &str) -> Result<Option<TagFilter>, String> {
+        self.inner.guild_filter(guild).await
+    }
+
+    async fn set_guild_filter(&self, guild: &str, filter: Option<&TagFilter>) -> Result<(), String> {
+        self.inner.set_guild_filter(guild, filter).await
+    }
+
+    async fn guild_packs(&self, guild: &str) -> Result<Option<Vec<String>>, String> {
+        self.inner.guild_packs(guild).await
+    }
+
+    async fn set_guild_packs(&self, guild: &str, packs: Option<&[String]>) -> Result<(), String> {
+        self.inner.set_guild_packs(guild, packs).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parse_set;
+    use crate::packs::resolve_packs;
+    use crate::sentences::{SentenceOptions, generate_sentences, resolve_filter};
+    use crate::storage::memory::MemoryStore;
+
+    /// Sentences load fine but history and guild settings are unreachable
+    struct Unreachable {
+        inner: MemoryStore
+    }
+
+    fn unreachable<T>() -> Result<T, String> {
+        Err("The database went away".to_owned())
+    }
+
+    #[async_trait]
+    impl SentenceStore for Unreachable {
+        async fn fetch(&self, sentence_type: SentenceType) -> Result<Vec<Sentence>, String> { self.inner.fetch(sentence_type).await }
+        async fn add(&self, sentence: &Sentence) -> Result<u64, String> { self.inner.add(sentence).await }
+        async fn remove(&self, id: u64) -> Result<bool, String> { self.inner.remove(id).await }
+        async fn list(&self) -> Result<Vec<Sentence>, String> { self.inner.list().await }
+        async fn set_tags(&self, id: u64, tags: &[String]) -> Result<bool, String> { self.inner.set_tags(id, tags).await }
+        async fn history(&self, _: &str, _: usize) -> Result<Vec<u64>, String> { unreachable() }
+        async fn record_history(&self, _: &str, _: &[u64], _: usize) -> Result<(), String> { unreachable() }
+        async fn add_submission(&self, submission: &Submission) -> Result<u64, String> { self.inner.add_submission(submission).await }
+        async fn update_submission(&self, submission: &Submission, from: SubmissionStatus) -> Result<bool, String> { self.inner.update_submission(submission, from).await }
+        async fn submission(&self, id: u64) -> Result<Option<Submission>, String> { self.inner.submission(id).await }
+        async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String> { self.inner.submissions(status).await }
+        async fn packs(&self) -> Result<Vec<PackManifest>, String> { self.inner.packs().await }
+        async fn save_pack(&self, manifest: &PackManifest) -> Result<(), String> { self.inner.save_pack(manifest).await }
+        async fn remove_pack(&self, name: &str) -> Result<bool, String> { self.inner.remove_pack(name).await }
+        async fn guild_filter(&self, _: &str) -> Result<Option<TagFilter>, String> { unreachable() }
+        async fn set_guild_filter(&self, _: &str, _: Option<&TagFilter>) -> Result<(), String> { unreachable() }
+        async fn guild_packs(&self, _: &str) -> Result<Option<Vec<String>>, String> { unreachable() }
+        async fn set_guild_packs(&self, _: &str, _: Option<&[String]>) -> Result<(), String> { unreachable() }
+    }
+
+    #[tokio::test]
+    async fn sentences_work_without_history_or_guild_settings() {
+        let inner = MemoryStore::new();
+        inner.add(&Sentence {
+            id: 0,
+            sentence_type: SentenceType::PronounsOnly,
+            text: "Then [subjective] waved.".to_owned(),
+            tags: vec![],
+            pack: None,
+            language: "en".to_owned(),
+            submitter: None
+        }).await.unwrap();
+        let store = CachedStore::new(Arc::new(Unreachable { inner })).await.unwrap();
+
+        assert_eq!(resolve_filter(&store, None, Some("1")).await, TagFilter::default());
+        assert!(resolve_packs(&store, None, Some("1")).await.unwrap().is_empty());
+
+        let options = SentenceOptions { requester: Some("ada".to_owned()), count: 1, ..SentenceOptions::default() };
+        let generated = generate_sentences(vec![], vec![parse_set("she").unwrap()], &store, &options).await.unwrap();
+        assert_eq!(generated.sentences, ["Then she waved."]);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::sync::RwLock;
+
+use crate::packs::PackManifest;
+use crate::sentences::{SentenceType, TagFilter};
+use crate::storage::{Sentence, SentenceStore};
+use crate::submissions::{Submission, SubmissionStatus};
+
+/// Everything the file backend saves
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Contents {
+    sentences: Vec<Sentence>,
+    guild_filters: HashMap<String, TagFilter>,
+    /// Most recent first
+    history: HashMap<String, Vec<u64>>,
+    submissions: Vec<Submission>,
+    packs: Vec<PackManifest>,
+    guild_packs: HashMap<String, Vec<String>>
+}
+
+/// Keeps everything in memory, optionally backed by a JSON file that gets
+/// rewritten on every change
+#[derive(Default)]
+pub struct MemoryStore {
+    contents: RwLock<Contents>,
+    path: Option<String>
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// A missing file is treated as empty and gets created on the first change
+    pub async fn open(path: &str) -> Result<MemoryStore, String> {
+        let contents = match fs::read_to_string(path).await {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|error| format!("{path}: {error}"))?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Contents::default(),
+            Err(error) => return Err(format!("{path}: {error}"))
+        };
+        Ok(MemoryStore { contents: RwLock::new(contents), path: Some(path.to_owned()) })
+    }
+
+    async fn save(&self, contents: &Contents) -> Result<(), String> {
+        match &self.path {
+            Some(path) => {
+                let contents = serde_json::to_string_pretty(contents).map_err(|error| error.to_string())?;
+                fs::write(path, contents).await.map_err(|error| format!("{path}: {error}"))
+            }
+            None => Ok(())
+        }
+    }
+}
+
+// Tags are a set in the database backends, so they are here too
+fn unique(tags: &[String]) -> Vec<String> {
+    let mut tags = tags.to_vec();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+#[async_trait]
+impl SentenceStore for MemoryStore {
+    async fn fetch(&self, sentence_type: SentenceType) -> Result<Vec<Sentence>, String> {
+        let contents = self.contents.read().await;
+        Ok(contents.sentences.iter().filter(|sentence| sentence.sentence_type == sentence_type).cloned().collect())
+    }
+
+    async fn add(&self, sentence: &Sentence) -> Result<u64, String> {
+        let mut contents = self.contents.write().await;
+        let id = contents.sentences.iter().map(|sentence| sentence.id).max().unwrap_or(0) + 1;
+        contents.sentences.push(Sentence { id, tags: unique(&sentence.tags), ..sentence.clone() });
+        self.save(&contents).await?;
+        Ok(id)
+    }
+
+    async fn remove(&self, id: u64) -> Result<bool, String> {
+        let mut contents = self.contents.write().await;
+        let before = contents.sentences.len();
+        contents.sentences.retain(|sentence| sentence.id != id);
+        if contents.sentences.len() == before {
+            return Ok(false);
+        }
+        self.save(&contents).await?;
+        Ok(true)
+    }
+
+    async fn list(&self) -> Result<Vec<Sentence>, String> {
+        Ok(self.contents.read().await.sentences.clone())
+    }
+
+    async fn set_tags(&self, id: u64, tags: &[String]) -> Result<bool, String> {
+        let mut contents = self.contents.write().await;
+        match contents.sentences.iter_mut().find(|sentence| sentence.id == id) {
+            Some(sentence) => sentence.tags = unique(tags),
+            None => return Ok(false)
+        }
+        self.save(&contents).await?;
+        Ok(true)
+    }
+
+    async fn history(&self, requester: &str, limit: usize) -> Result<Vec<u64>, String> {
+        let contents = self.contents.read().await;
+        Ok(contents.history.get(requester).map_or(Vec::new(), |history| {
+            history.iter().take(limit).copied().collect()
+        }))
+    }
+
+    async fn record_history(&self, requester: &str, ids: &[u64], keep: usize) -> Result<(), String> {
+        let mut contents = self.contents.write().await;
+        let history = contents.history.entry(requester.to_owned()).or_default();
+        for id in ids {
+            history.insert(0, *id);
+        }
+        history.truncate(keep);
+        self.save(&contents).await
+    }
+
+    async fn add_submission(&self, submission: &Submission) -> Result<u64, String> {
+        let mut contents = self.contents.write().await;
+        let id = contents.submissions.iter().map(|submission| submission.id).max().unwrap_or(0) + 1;
+        let mut submission = submission.clone();
+        submission.id = id;
+        contents.submissions.push(submission);
+        self.save(&contents).await?;
+        Ok(id)
+    }
+
+    async fn update_submission(&self, submission: &Submission, from: SubmissionStatus) -> Result<bool, String> {
+        let mut contents = self.contents.write().await;
+        match contents.submissions.iter_mut().find(|existing| existing.id == submission.id && existing.status == from) {
+            Some(existing) => *existing = submission.clone(),
+            None => return Ok(false)
+        }
+        self.save(&contents).await?;
+        Ok(true)
+    }
+
+    async fn submission(&self, id: u64) -> Result<Option<Submission>, String> {
+        let contents = self.contents.read().await;
+        Ok(contents.submissions.iter().find(|submission| submission.id == id).cloned())
+    }
+
+    async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String> {
+        let contents = self.contents.read().await;
+        Ok(contents.submissions.iter()
+            .filter(|submission| status.is_none_or(|status| submission.status == status))
+            .cloned()
+            .collect())
+    }
+
+    async fn guild_filter(&self, guild: &str) -> Result<Option<TagFilter>, String> {
+        Ok(self.contents.read().await.guild_filters.get(guild).cloned())
+    }
+
+    async fn set_guild_filter(&self, guild: &str, filter: Option<&TagFilter>) -> Result<(), String> {
+        let mut contents = self.contents.write().await;
+        match filter {
+            Some(filter) => contents.guild_filters.insert(guild.to_owned(), filter.clone()),
+            None => contents.guild_filters.remove(guild)
+        };
+        self.save(&contents).await
+    }
+
+    async fn packs(&self) -> Result<Vec<PackManifest>, String> {
+        let mut packs = self.contents.read().await.packs.clone();
+        packs.sort_by(|first, second| first.name.cmp(&second.name));
+        Ok(packs)
+    }
+
+    async fn save_pack(&self, manifest: &PackManifest) -> Result<(), String> {
+        let mut contents = self.contents.write().await;
+        contents.packs.retain(|pack| pack.name != manifest.name);
+        contents.packs.push(manifest.clone());
+        self.save(&contents).await
+    }
+
+    async fn remove_pack(&self, name: &str) -> Result<bool, String> {
+        let mut contents = self.contents.write().await;
+        let before = contents.packs.len();
+        contents.packs.retain(|pack| pack.name != name);
+        let removed = contents.packs.len() != before;
+        contents.sentences.retain(|sentence| sentence.pack.as_deref() != Some(name));
+        self.save(&contents).await?;
+        Ok(removed)
+    }
+
+    async fn guild_packs(&self, guild: &str) -> Result<Option<Vec<String>>, String> {
+        Ok(self.contents.read().await.guild_packs.get(guild).cloned())
+    }
+
+    async fn set_guild_packs(&self, guild: &str, packs: Option<&[String]>) -> Result<(), String> {
+        let mut contents = self.contents.write().await;
+        match packs {
+            Some(packs) => contents.guild_packs.insert(guild.to_owned(), packs.to_vec()),
+            None => contents.guild_packs.remove(guild)
+        };
+        self.save(&contents).await
+    }
+}
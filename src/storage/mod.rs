@@ -0,0 +1,257 @@
+pub mod cache;
+pub mod memory;
+pub mod mysql;
+pub mod sqlite;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Template;
+use crate::locale::default_language;
+use crate::packs::PackManifest;
+use crate::sentences::{SentenceType, TagFilter};
+use crate::submissions::{Submission, SubmissionStatus};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sentence {
+    pub id: u64,
+    pub sentence_type: SentenceType,
+    pub text: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The pack it came from, sentences added any other way have none
+    #[serde(default)]
+    pub pack: Option<String>,
+    /// A language tag, see `locale::normalise_language`
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// Who suggested it, for sentences that came from a submission
+    #[serde(default)]
+    pub submitter: Option<String>
+}
+
+/// A sentence with its template already parsed, the template is shared
+/// between clones
+#[derive(Clone, Debug)]
+pub struct CompiledSentence {
+    pub sentence: Sentence,
+    pub template: Arc<Template>
+}
+
+impl CompiledSentence {
+    pub fn new(sentence: Sentence) -> CompiledSentence {
+        let template = Arc::new(Template::compile(&sentence.text));
+        CompiledSentence { sentence, template }
+    }
+}
+
+/// Somewhere to keep sentences. Errors are strings so they can go straight
+/// back to whoever made the request
+#[async_trait]
+pub trait SentenceStore: Send + Sync {
+    async fn fetch(&self, sentence_type: SentenceType) -> Result<Vec<Sentence>, String>;
+    /// Like `fetch`, with the templates parsed ready to fill in. Stores that
+    /// keep sentences in memory can parse them once when they're loaded
+    async fn fetch_compiled(&self, sentence_type: SentenceType) -> Result<Vec<CompiledSentence>, String> {
+        Ok(self.fetch(sentence_type).await?.into_iter().map(CompiledSentence::new).collect())
+    }
+    /// Returns the new sentence's ID, the one it has is ignored
+    async fn add(&self, sentence: &Sentence) -> Result<u64, String>;
+    /// Returns whether there was a sentence with that ID to remove
+    async fn remove(&self, id: u64) -> Result<bool, String>;
+    async fn list(&self) -> Result<Vec<Sentence>, String>;
+    /// Replaces all of a sentence's tags, returns whether the sentence exists
+    async fn set_tags(&self, id: u64, tags: &[String]) -> Result<bool, String>;
+    /// Picks up sentences changed behind the store's back, only stores that
+    /// keep a copy of them need to do anything
+    async fn refresh(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// The sentences most recently served to a requester, most recent first
+    async fn history(&self, requester: &str, limit: usize) -> Result<Vec<u64>, String>;
+    /// Adds to a requester's history, forgetting all but the last `keep`
+    async fn record_history(&self, requester: &str, ids: &[u64], keep: usize) -> Result<(), String>;
+
+    /// Returns the new submission's ID, the one it has is ignored
+    async fn add_submission(&self, submission: &Submission) -> Result<u64, String>;
+    /// Only saves it if the stored one's status is still `from`, returns
+    /// whether it did
+    async fn update_submission(&self, submission: &Submission, from: SubmissionStatus) -> Result<bool, String>;
+    async fn submission(&self, id: u64) -> Result<Option<Submission>, String>;
+    /// Every submission with that status (or every submission at all), oldest first
+    async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String>;
+
+    async fn packs(&self) -> Result<Vec<PackManifest>, String>;
+    /// Adds or replaces a pack's manifest, its sentences are added separately
+    async fn save_pack(&self, manifest: &PackManifest) -> Result<(), String>;
+    /// Removes a pack and every sentence in it, returns whether it was installed
+    async fn remove_pack(&self, name: &str) -> Result<bool, String>;
+
+    async fn guild_filter(&self, guild: &str) -> Result<Option<TagFilter>, String>;
+    /// `None` clears the guild's default
+    async fn set_guild_filter(&self, guild: &str, filter: Option<&TagFilter>) -> Result<(), String>;
+    async fn guild_packs(&self, guild: &str) -> Result<Option<Vec<String>>, String>;
+    /// `None` goes back to no packs
+    async fn set_guild_packs(&self, guild: &str, packs: Option<&[String]>) -> Result<(), String>;
+}
+
+/// Which backend sentences are kept in. MySQL uses the `database` section of
+/// the config, the others need nothing else. Backends without settings are
+/// still `{}` so that unknown fields are caught for them too
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "backend", deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreConfig {
+    Mysql {},
+    Sqlite {
+        path: String
+    },
+    /// Starts empty and forgets everything on exit, good for tests
+    Memory {},
+    /// Like `Memory`, but loaded from and saved back to a JSON file
+    File {
+        path: String
+    }
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig::Mysql {}
+    }
+}
+
+pub async fn open(config: &StoreConfig, mysql: mysql_async::Opts) -> Result<Box<dyn SentenceStore>, String> {
+    Ok(match config {
+        StoreConfig::Mysql {} => Box::new(mysql::MySqlStore::open(mysql).await?),
+        StoreConfig::Sqlite { path } => Box::new(sqlite::SqliteStore::open(path)?),
+        StoreConfig::Memory {} => Box::new(memory::MemoryStore::new()),
+        StoreConfig::File { path } => Box::new(memory::MemoryStore::open(path).await?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(text: &str, tags: &[&str]) -> Sentence {
+        Sentence {
+            id: 0,
+            sentence_type: SentenceType::NamesPronouns,
+            text: text.to_owned(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            pack: None,
+            language: "en".to_owned(),
+            submitter: None
+        }
+    }
+
+    async fn tags(store: &dyn SentenceStore, id: u64) -> Vec<String> {
+        let sentence = store.list().await.unwrap().into_iter().find(|sentence| sentence.id == id).unwrap();
+        let mut tags = sentence.tags;
+        tags.sort();
+        tags
+    }
+
+    // Every backend should behave the same, MySQL needs a server so it isn't
+    // covered here
+    async fn conformance(store: &dyn SentenceStore) {
+        let first = store.add(&sentence("[name] waved.", &["happy", "short", "happy"])).await.unwrap();
+        let second = store.add(&Sentence {
+            sentence_type: SentenceType::PronounsOnly,
+            pack: Some("fantasy".to_owned()),
+            language: "pt-BR".to_owned(),
+            submitter: Some("ada".to_owned()),
+            ..sentence("[subject] waved.", &[])
+        }).await.unwrap();
+        assert_ne!(first, second);
+        assert_eq!(tags(store, first).await, ["happy", "short"]);
+
+        let fetched = store.fetch(SentenceType::PronounsOnly).await.unwrap();
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].id, second);
+        assert_eq!(fetched[0].text, "[subject] waved.");
+        assert_eq!(fetched[0].pack.as_deref(), Some("fantasy"));
+        assert_eq!(fetched[0].language, "pt-BR");
+        assert_eq!(fetched[0].submitter.as_deref(), Some("ada"));
+        assert_eq!(store.list().await.unwrap().len(), 2);
+
+        assert!(store.set_tags(first, &["sad".to_owned(), "sad".to_owned()]).await.unwrap());
+        assert_eq!(tags(store, first).await, ["sad"]);
+        assert!(!store.set_tags(1000, &[]).await.unwrap());
+
+        assert!(store.remove(first).await.unwrap());
+        assert!(!store.remove(first).await.unwrap());
+        assert!(store.fetch(SentenceType::NamesPronouns).await.unwrap().is_empty());
+
+        store.record_history("ada", &[1, 2], 3).await.unwrap();
+        store.record_history("ada", &[3, 4], 3).await.unwrap();
+        assert_eq!(store.history("ada", 10).await.unwrap(), [4, 3, 2]);
+        assert_eq!(store.history("ada", 2).await.unwrap(), [4, 3]);
+        assert!(store.history("grace", 10).await.unwrap().is_empty());
+
+        let submission = Submission {
+            id: 0,
+            text: "[name] waved.".to_owned(),
+            sentence_type: SentenceType::NamesPronouns,
+            tags: vec![],
+            language: "en".to_owned(),
+            submitter: "ada".to_owned(),
+            status: SubmissionStatus::Pending,
+            sentence: None,
+            history: vec![]
+        };
+        let id = store.add_submission(&submission).await.unwrap();
+        let mut approved = store.submission(id).await.unwrap().unwrap();
+        assert_eq!(approved.id, id);
+        approved.status = SubmissionStatus::Approved;
+        assert!(store.update_submission(&approved, SubmissionStatus::Pending).await.unwrap());
+        assert!(!store.update_submission(&approved, SubmissionStatus::Pending).await.unwrap());
+        assert_eq!(store.submissions(Some(SubmissionStatus::Approved)).await.unwrap().len(), 1);
+        assert!(store.submissions(Some(SubmissionStatus::Pending)).await.unwrap().is_empty());
+        assert!(store.submission(1000).await.unwrap().is_none());
+
+        let mut manifest = PackManifest {
+            name: "fantasy".to_owned(),
+            version: "1.0.0".to_owned(),
+            author: "ada".to_owned(),
+            language: "en".to_owned(),
+            license: "MIT".to_owned(),
+            description: None
+        };
+        store.save_pack(&manifest).await.unwrap();
+        manifest.version = "1.1.0".to_owned();
+        store.save_pack(&manifest).await.unwrap();
+        let packs = store.packs().await.unwrap();
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].version, "1.1.0");
+        assert!(store.remove_pack("fantasy").await.unwrap());
+        assert!(!store.remove_pack("fantasy").await.unwrap());
+        assert!(store.list().await.unwrap().is_empty());
+
+        let filter = TagFilter { include: vec!["happy".to_owned()], exclude: vec![] };
+        assert_eq!(store.guild_filter("1").await.unwrap(), None);
+        store.set_guild_filter("1", Some(&filter)).await.unwrap();
+        assert_eq!(store.guild_filter("1").await.unwrap(), Some(filter));
+        store.set_guild_filter("1", None).await.unwrap();
+        assert_eq!(store.guild_filter("1").await.unwrap(), None);
+
+        assert_eq!(store.guild_packs("1").await.unwrap(), None);
+        store.set_guild_packs("1", Some(&["fantasy".to_owned()])).await.unwrap();
+        assert_eq!(store.guild_packs("1").await.unwrap(), Some(vec!["fantasy".to_owned()]));
+        store.set_guild_packs("1", None).await.unwrap();
+        assert_eq!(store.guild_packs("1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn memory_conforms() {
+        conformance(&memory::MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn sqlite_conforms() {
+        conformance(&sqlite::SqliteStore::open(":memory:").unwrap()).await;
+    }
+}
@@ -0,0 +1,314 @@
+use async_trait::async_trait;
+use mysql_async::{Opts, Pool, TxOpts, prelude::Queryable};
+
+use crate::locale::default_language;
+use crate::packs::PackManifest;
+use crate::sentences::{SentenceType, TagFilter};
+use crate::storage::{Sentence, SentenceStore};
+use crate::submissions::{Submission, SubmissionStatus};
+
+// `Sentences` has always been managed by hand, so it's only created when
+// it's missing and existing databases keep working as they are
+const SETUP: &str = "CREATE TABLE IF NOT EXISTS Sentences (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    Sentence TEXT NOT NULL,
+    Type TINYINT UNSIGNED NOT NULL
+);
+CREATE TABLE IF NOT EXISTS SentenceTags (
+    Sentence BIGINT UNSIGNED NOT NULL,
+    Tag VARCHAR(64) NOT NULL,
+    PRIMARY KEY (Sentence, Tag)
+);
+CREATE TABLE IF NOT EXISTS SentenceHistory (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    Requester VARCHAR(64) NOT NULL,
+    Sentence BIGINT UNSIGNED NOT NULL,
+    INDEX (Requester)
+);
+CREATE TABLE IF NOT EXISTS Submissions (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    Status VARCHAR(16) NOT NULL,
+    Data TEXT NOT NULL,
+    INDEX (Status)
+);
+CREATE TABLE IF NOT EXISTS GuildFilters (
+    Guild VARCHAR(64) NOT NULL PRIMARY KEY,
+    Filter TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS Packs (
+    Name VARCHAR(64) NOT NULL PRIMARY KEY,
+    Manifest TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS PackSentences (
+    Sentence BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+    Pack VARCHAR(64) NOT NULL,
+    INDEX (Pack)
+);
+CREATE TABLE IF NOT EXISTS GuildPacks (
+    Guild VARCHAR(64) NOT NULL PRIMARY KEY,
+    Packs TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS SentenceLanguages (
+    Sentence BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+    Language VARCHAR(35) NOT NULL
+);
+CREATE TABLE IF NOT EXISTS SentenceSubmitters (
+    Sentence BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+    Submitter VARCHAR(64) NOT NULL
+);";
+
+// Tags are joined with the unit separator so they can be read in one query
+const SELECT_SENTENCES: &str = "SELECT s.ID, s.Sentence, s.Type, GROUP_CONCAT(t.Tag SEPARATOR '\u{1f}'), MAX(p.Pack), MAX(l.Language), MAX(a.Submitter)
+    FROM Sentences s LEFT JOIN SentenceTags t ON t.Sentence = s.ID LEFT JOIN PackSentences p ON p.Sentence = s.ID
+    LEFT JOIN SentenceLanguages l ON l.Sentence = s.ID LEFT JOIN SentenceSubmitters a ON a.Sentence = s.ID";
+
+type SentenceRow = (u64, String, u8, Option<String>, Option<String>, Option<String>, Option<String>);
+
+// Sentences from before languages existed have no row, they're all English
+fn from_row((id, text, sentence_type, tags, pack, language, submitter): SentenceRow) -> Sentence {
+    Sentence {
+        id,
+        sentence_type: SentenceType::from_id(sentence_type),
+        text,
+        tags: tags.map_or(Vec::new(), |tags| tags.split('\u{1f}').map(|tag| tag.to_owned()).collect()),
+        pack,
+        language: language.unwrap_or_else(default_language),
+        submitter
+    }
+}
+
+// Submissions are kept as JSON, only the status needs to be searchable
+fn submission_from_row((id, data): (u64, String)) -> Result<Submission, String> {
+    let mut submission: Submission = serde_json::from_str(&data).map_err(|error| error.to_string())?;
+    submission.id = id;
+    Ok(submission)
+}
+
+/// A hand-made `Sentences` table needs an `AUTO_INCREMENT` primary key
+/// called `ID`, as new sentences get their ID from `LAST_INSERT_ID()`
+pub struct MySqlStore {
+    pool: Pool
+}
+
+impl MySqlStore {
+    pub async fn open(opts: Opts) -> Result<MySqlStore, String> {
+        let pool = Pool::new(opts);
+        let mut conn = pool.get_conn().await.map_err(|error| error.to_string())?;
+        conn.query_drop(SETUP).await.map_err(|error| error.to_string())?;
+        Ok(MySqlStore { pool })
+    }
+
+    async fn conn(&self) -> Result<mysql_async::Conn, String> {
+        self.pool.get_conn().await.map_err(|error| error.to_string())
+    }
+}
+
+#[async_trait]
+impl SentenceStore for MySqlStore {
+    async fn fetch(&self, sentence_type: SentenceType) -> Result<Vec<Sentence>, String> {
+        let mut conn = self.conn().await?;
+        let rows: Vec<SentenceRow> = conn.exec(
+            format!("{SELECT_SENTENCES} WHERE s.Type=? GROUP BY s.ID"),
+            (sentence_type.id(),)
+        ).await.map_err(|error| error.to_string())?;
+        Ok(rows.into_iter().map(from_row).collect())
+    }
+
+    // A transaction dropped early is rolled back, so a failure part way
+    // through never leaves half a sentence behind
+    async fn add(&self, sentence: &Sentence) -> Result<u64, String> {
+        let mut conn = self.conn().await?;
+        let mut transaction = conn.start_transaction(TxOpts::default()).await.map_err(|error| error.to_string())?;
+        transaction.exec_drop(
+            "INSERT INTO Sentences (Sentence, Type) VALUES (?, ?)",
+            (&sentence.text, sentence.sentence_type.id())
+        ).await.map_err(|error| error.to_string())?;
+        let id = transaction.last_insert_id().ok_or_else(|| "MySQL didn't return the new sentence's ID".to_owned())?;
+        transaction.exec_batch(
+            "INSERT IGNORE INTO SentenceTags (Sentence, Tag) VALUES (?, ?)",
+            sentence.tags.iter().map(|tag| (id, tag))
+        ).await.map_err(|error| error.to_string())?;
+        if let Some(pack) = &sentence.pack {
+            transaction.exec_drop("INSERT INTO PackSentences (Sentence, Pack) VALUES (?, ?)", (id, pack))
+                .await.map_err(|error| error.to_string())?;
+        }
+        transaction.exec_drop("INSERT INTO SentenceLanguages (Sentence, Language) VALUES (?, ?)", (id, &sentence.language))
+            .await.map_err(|error| error.to_string())?;
+        if let Some(submitter) = &sentence.submitter {
+            transaction.exec_drop("INSERT INTO SentenceSubmitters (Sentence, Submitter) VALUES (?, ?)", (id, submitter))
+                .await.map_err(|error| error.to_string())?;
+        }
+        transaction.commit().await.map_err(|error| error.to_string())?;
+        Ok(id)
+    }
+
+    async fn remove(&self, id: u64) -> Result<bool, String> {
+        let mut conn = self.conn().await?;
+        let mut transaction = conn.start_transaction(TxOpts::default()).await.map_err(|error| error.to_string())?;
+        transaction.exec_drop("DELETE FROM Sentences WHERE ID=?", (id,)).await.map_err(|error| error.to_string())?;
+        let removed = transaction.affected_rows() > 0;
+        transaction.exec_drop("DELETE FROM SentenceTags WHERE Sentence=?", (id,)).await.map_err(|error| error.to_string())?;
+        transaction.exec_drop("DELETE FROM PackSentences WHERE Sentence=?", (id,)).await.map_err(|error| error.to_string())?;
+        transaction.exec_drop("DELETE FROM SentenceLanguages WHERE Sentence=?", (id,)).await.map_err(|error| error.to_string())?;
+        transaction.exec_drop("DELETE FROM SentenceSubmitters WHERE Sentence=?", (id,)).await.map_err(|error| error.to_string())?;
+        transaction.commit().await.map_err(|error| error.to_string())?;
+        Ok(removed)
+    }
+
+    async fn list(&self) -> Result<Vec<Sentence>, String> {
+        let mut conn = self.conn().await?;
+        let rows: Vec<SentenceRow> = conn.query(format!("{SELECT_SENTENCES} GROUP BY s.ID"))
+            .await.map_err(|error| error.to_string())?;
+        Ok(rows.into_iter().map(from_row).collect())
+    }
+
+    async fn set_tags(&self, id: u64, tags: &[String]) -> Result<bool, String> {
+        let mut conn = self.conn().await?;
+        let mut transaction = conn.start_transaction(TxOpts::default()).await.map_err(|error| error.to_string())?;
+        let exists: Option<u64> = transaction.exec_first("SELECT ID FROM Sentences WHERE ID=? FOR UPDATE", (id,))
+            .await.map_err(|error| error.to_string())?;
+        if exists.is_none() {
+            return Ok(false);
+        }
+        transaction.exec_drop("DELETE FROM SentenceTags WHERE Sentence=?", (id,)).await.map_err(|error| error.to_string())?;
+        transaction.exec_batch(
+            "INSERT IGNORE INTO SentenceTags (Sentence, Tag) VALUES (?, ?)",
+            tags.iter().map(|tag| (id, tag))
+        ).await.map_err(|error| error.to_string())?;
+        transaction.commit().await.map_err(|error| error.to_string())?;
+        Ok(true)
+    }
+
+    async fn history(&self, requester: &str, limit: usize) -> Result<Vec<u64>, String> {
+        let mut conn = self.conn().await?;
+        conn.exec(
+            "SELECT Sentence FROM SentenceHistory WHERE Requester=? ORDER BY ID DESC LIMIT ?",
+            (requester, limit as u64)
+        ).await.map_err(|error| error.to_string())
+    }
+
+    async fn record_history(&self, requester: &str, ids: &[u64], keep: usize) -> Result<(), String> {
+        let mut conn = self.conn().await?;
+        conn.exec_batch(
+            "INSERT INTO SentenceHistory (Requester, Sentence) VALUES (?, ?)",
+            ids.iter().map(|id| (requester, id))
+        ).await.map_err(|error| error.to_string())?;
+        // Find the oldest entry worth keeping and drop everything before it
+        let oldest: Option<u64> = conn.exec_first(
+            "SELECT ID FROM SentenceHistory WHERE Requester=? ORDER BY ID DESC LIMIT 1 OFFSET ?",
+            (requester, keep.saturating_sub(1) as u64)
+        ).await.map_err(|error| error.to_string())?;
+        if let Some(oldest) = oldest {
+            conn.exec_drop("DELETE FROM SentenceHistory WHERE Requester=? AND ID<?", (requester, oldest))
+                .await.map_err(|error| error.to_string())?;
+        }
+        Ok(())
+    }
+
+    async fn add_submission(&self, submission: &Submission) -> Result<u64, String> {
+        let mut conn = self.conn().await?;
+        let data = serde_json::to_string(submission).map_err(|error| error.to_string())?;
+        conn.exec_drop(
+            "INSERT INTO Submissions (Status, Data) VALUES (?, ?)",
+            (submission.status.name(), data)
+        ).await.map_err(|error| error.to_string())?;
+        conn.last_insert_id().ok_or_else(|| "MySQL didn't return the new submission's ID".to_owned())
+    }
+
+    // Every review adds to the history, so a matching row always changes and
+    // is counted as affected
+    async fn update_submission(&self, submission: &Submission, from: SubmissionStatus) -> Result<bool, String> {
+        let mut conn = self.conn().await?;
+        let data = serde_json::to_string(submission).map_err(|error| error.to_string())?;
+        conn.exec_drop(
+            "UPDATE Submissions SET Status=?, Data=? WHERE ID=? AND Status=?",
+            (submission.status.name(), data, submission.id, from.name())
+        ).await.map_err(|error| error.to_string())?;
+        Ok(conn.affected_rows() > 0)
+    }
+
+    async fn submission(&self, id: u64) -> Result<Option<Submission>, String> {
+        let mut conn = self.conn().await?;
+        let row: Option<(u64, String)> = conn.exec_first("SELECT ID, Data FROM Submissions WHERE ID=?", (id,))
+            .await.map_err(|error| error.to_string())?;
+        row.map(submission_from_row).transpose()
+    }
+
+    async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String> {
+        let mut conn = self.conn().await?;
+        let rows: Vec<(u64, String)> = match status {
+            Some(status) => conn.exec("SELECT ID, Data FROM Submissions WHERE Status=? ORDER BY ID", (status.name(),)).await,
+            None => conn.query("SELECT ID, Data FROM Submissions ORDER BY ID").await
+        }.map_err(|error| error.to_string())?;
+        rows.into_iter().map(submission_from_row).collect()
+    }
+
+    async fn guild_filter(&self, guild: &str) -> Result<Option<TagFilter>, String> {
+        let mut conn = self.conn().await?;
+        let filter: Option<String> = conn.exec_first("SELECT Filter FROM GuildFilters WHERE Guild=?", (guild,))
+            .await.map_err(|error| error.to_string())?;
+        match filter {
+            Some(filter) => serde_json::from_str(&filter).map(Some).map_err(|error| error.to_string()),
+            None => Ok(None)
+        }
+    }
+
+    async fn set_guild_filter(&self, guild: &str, filter: Option<&TagFilter>) -> Result<(), String> {
+        let mut conn = self.conn().await?;
+        match filter {
+            Some(filter) => {
+                let filter = serde_json::to_string(filter).map_err(|error| error.to_string())?;
+                conn.exec_drop("REPLACE INTO GuildFilters (Guild, Filter) VALUES (?, ?)", (guild, filter)).await
+            }
+            None => conn.exec_drop("DELETE FROM GuildFilters WHERE Guild=?", (guild,)).await
+        }.map_err(|error| error.to_string())
+    }
+
+    async fn packs(&self) -> Result<Vec<PackManifest>, String> {
+        let mut conn = self.conn().await?;
+        let manifests: Vec<String> = conn.query("SELECT Manifest FROM Packs ORDER BY Name")
+            .await.map_err(|error| error.to_string())?;
+        manifests.iter().map(|manifest| serde_json::from_str(manifest).map_err(|error| error.to_string())).collect()
+    }
+
+    async fn save_pack(&self, manifest: &PackManifest) -> Result<(), String> {
+        let mut conn = self.conn().await?;
+        let data = serde_json::to_string(manifest).map_err(|error| error.to_string())?;
+        conn.exec_drop("REPLACE INTO Packs (Name, Manifest) VALUES (?, ?)", (&manifest.name, data))
+            .await.map_err(|error| error.to_string())
+    }
+
+    async fn remove_pack(&self, name: &str) -> Result<bool, String> {
+        let mut conn = self.conn().await?;
+        conn.exec_drop("DELETE FROM Packs WHERE Name=?", (name,)).await.map_err(|error| error.to_string())?;
+        let removed = conn.affected_rows() > 0;
+        conn.exec_drop(
+            "DELETE s, t, l FROM Sentences s JOIN PackSentences p ON p.Sentence = s.ID
+                LEFT JOIN SentenceTags t ON t.Sentence = s.ID LEFT JOIN SentenceLanguages l ON l.Sentence = s.ID WHERE p.Pack=?",
+            (name,)
+        ).await.map_err(|error| error.to_string())?;
+        conn.exec_drop("DELETE FROM PackSentences WHERE Pack=?", (name,)).await.map_err(|error| error.to_string())?;
+        Ok(removed)
+    }
+
+    async fn guild_packs(&self, guild: &str) -> Result<Option<Vec<String>>, String> {
+        let mut conn = self.conn().await?;
+        let packs: Option<String> = conn.exec_first("SELECT Packs FROM GuildPacks WHERE Guild=?", (guild,))
+            .await.map_err(|error| error.to_string())?;
+        match packs {
+            Some(packs) => serde_json::from_str(&packs).map(Some).map_err(|error| error.to_string()),
+            None => Ok(None)
+        }
+    }
+
+    async fn set_guild_packs(&self, guild: &str, packs: Option<&[String]>) -> Result<(), String> {
+        let mut conn = self.conn().await?;
+        match packs {
+            Some(packs) => {
+                let packs = serde_json::to_string(packs).map_err(|error| error.to_string())?;
+                conn.exec_drop("REPLACE INTO GuildPacks (Guild, Packs) VALUES (?, ?)", (guild, packs)).await
+            }
+            None => conn.exec_drop("DELETE FROM GuildPacks WHERE Guild=?", (guild,)).await
+        }.map_err(|error| error.to_string())
+    }
+}
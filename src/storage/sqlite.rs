@@ -0,0 +1,324 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+
+use crate::locale::default_language;
+use crate::packs::PackManifest;
+use crate::sentences::{SentenceType, TagFilter};
+use crate::storage::{Sentence, SentenceStore};
+use crate::submissions::{Submission, SubmissionStatus};
+
+const SETUP: &str = "CREATE TABLE IF NOT EXISTS Sentences (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    Sentence TEXT NOT NULL,
+    Type INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS SentenceTags (
+    Sentence INTEGER NOT NULL,
+    Tag TEXT NOT NULL,
+    PRIMARY KEY (Sentence, Tag)
+);
+CREATE TABLE IF NOT EXISTS SentenceHistory (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    Requester TEXT NOT NULL,
+    Sentence INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS SentenceHistoryRequester ON SentenceHistory (Requester);
+CREATE TABLE IF NOT EXISTS Submissions (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    Status TEXT NOT NULL,
+    Data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS GuildFilters (
+    Guild TEXT NOT NULL PRIMARY KEY,
+    Filter TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS Packs (
+    Name TEXT NOT NULL PRIMARY KEY,
+    Manifest TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS PackSentences (
+    Sentence INTEGER NOT NULL PRIMARY KEY,
+    Pack TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS PackSentencesPack ON PackSentences (Pack);
+CREATE TABLE IF NOT EXISTS GuildPacks (
+    Guild TEXT NOT NULL PRIMARY KEY,
+    Packs TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS SentenceLanguages (
+    Sentence INTEGER NOT NULL PRIMARY KEY,
+    Language TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS SentenceSubmitters (
+    Sentence INTEGER NOT NULL PRIMARY KEY,
+    Submitter TEXT NOT NULL
+);";
+
+const SELECT_SENTENCES: &str = "SELECT s.ID, s.Sentence, s.Type, group_concat(t.Tag, char(31)), max(p.Pack), max(l.Language), max(a.Submitter)
+    FROM Sentences s LEFT JOIN SentenceTags t ON t.Sentence = s.ID LEFT JOIN PackSentences p ON p.Sentence = s.ID
+    LEFT JOIN SentenceLanguages l ON l.Sentence = s.ID LEFT JOIN SentenceSubmitters a ON a.Sentence = s.ID";
+
+fn from_row(row: &Row) -> rusqlite::Result<Sentence> {
+    let tags: Option<String> = row.get(3)?;
+    // Sentences from before languages existed have no row, they're all English
+    let language: Option<String> = row.get(5)?;
+    Ok(Sentence {
+        id: row.get(0)?,
+        sentence_type: SentenceType::from_id(row.get(2)?),
+        text: row.get(1)?,
+        tags: tags.map_or(Vec::new(), |tags| tags.split('\u{1f}').map(|tag| tag.to_owned()).collect()),
+        pack: row.get(4)?,
+        language: language.unwrap_or_else(default_language),
+        submitter: row.get(6)?
+    })
+}
+
+fn insert_tags(conn: &Connection, id: u64, tags: &[String]) -> rusqlite::Result<()> {
+    let mut statement = conn.prepare("INSERT OR IGNORE INTO SentenceTags (Sentence, Tag) VALUES (?1, ?2)")?;
+    for tag in tags {
+        statement.execute(params![id, tag])?;
+    }
+    Ok(())
+}
+
+// Submissions are kept as JSON, only the status needs to be searchable
+fn submission_from_row((id, data): (u64, String)) -> Result<Submission, String> {
+    let mut submission: Submission = serde_json::from_str(&data).map_err(|error| error.to_string())?;
+    submission.id = id;
+    Ok(submission)
+}
+
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore, String> {
+        let conn = Connection::open(path).map_err(|error| error.to_string())?;
+        conn.execute_batch(SETUP).map_err(|error| error.to_string())?;
+        Ok(SqliteStore { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    // rusqlite blocks, so everything runs off the async threads
+    async fn with_conn<T, F>(&self, task: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().map_err(|error| error.to_string())?;
+            task(&conn).map_err(|error| error.to_string())
+        }).await.map_err(|error| error.to_string())?
+    }
+}
+
+#[async_trait]
+impl SentenceStore for SqliteStore {
+    async fn fetch(&self, sentence_type: SentenceType) -> Result<Vec<Sentence>, String> {
+        self.with_conn(move |conn| {
+            let mut statement = conn.prepare(&format!("{SELECT_SENTENCES} WHERE s.Type=?1 GROUP BY s.ID"))?;
+            let rows = statement.query_map(params![sentence_type.id()], from_row)?;
+            rows.collect()
+        }).await
+    }
+
+    async fn add(&self, sentence: &Sentence) -> Result<u64, String> {
+        let sentence = sentence.clone();
+        self.with_conn(move |conn| {
+            // Rolled back if it's dropped before the commit
+            let transaction = conn.unchecked_transaction()?;
+            transaction.execute("INSERT INTO Sentences (Sentence, Type) VALUES (?1, ?2)", params![sentence.text, sentence.sentence_type.id()])?;
+            let id = transaction.last_insert_rowid() as u64;
+            insert_tags(&transaction, id, &sentence.tags)?;
+            if let Some(pack) = sentence.pack {
+                transaction.execute("INSERT INTO PackSentences (Sentence, Pack) VALUES (?1, ?2)", params![id, pack])?;
+            }
+            transaction.execute("INSERT INTO SentenceLanguages (Sentence, Language) VALUES (?1, ?2)", params![id, sentence.language])?;
+            if let Some(submitter) = sentence.submitter {
+                transaction.execute("INSERT INTO SentenceSubmitters (Sentence, Submitter) VALUES (?1, ?2)", params![id, submitter])?;
+            }
+            transaction.commit()?;
+            Ok(id)
+        }).await
+    }
+
+    async fn remove(&self, id: u64) -> Result<bool, String> {
+        self.with_conn(move |conn| {
+            let transaction = conn.unchecked_transaction()?;
+            let removed = transaction.execute("DELETE FROM Sentences WHERE ID=?1", params![id])? > 0;
+            transaction.execute("DELETE FROM SentenceTags WHERE Sentence=?1", params![id])?;
+            transaction.execute("DELETE FROM PackSentences WHERE Sentence=?1", params![id])?;
+            transaction.execute("DELETE FROM SentenceLanguages WHERE Sentence=?1", params![id])?;
+            transaction.execute("DELETE FROM SentenceSubmitters WHERE Sentence=?1", params![id])?;
+            transaction.commit()?;
+            Ok(removed)
+        }).await
+    }
+
+    async fn list(&self) -> Result<Vec<Sentence>, String> {
+        self.with_conn(|conn| {
+            let mut statement = conn.prepare(&format!("{SELECT_SENTENCES} GROUP BY s.ID"))?;
+            let rows = statement.query_map([], from_row)?;
+            rows.collect()
+        }).await
+    }
+
+    async fn set_tags(&self, id: u64, tags: &[String]) -> Result<bool, String> {
+        let tags = tags.to_vec();
+        self.with_conn(move |conn| {
+            let transaction = conn.unchecked_transaction()?;
+            let exists = transaction.query_row("SELECT ID FROM Sentences WHERE ID=?1", params![id], |_| Ok(())).optional()?;
+            if exists.is_none() {
+                return Ok(false);
+            }
+            transaction.execute("DELETE FROM SentenceTags WHERE Sentence=?1", params![id])?;
+            insert_tags(&transaction, id, &tags)?;
+            transaction.commit()?;
+            Ok(true)
+        }).await
+    }
+
+    async fn history(&self, requester: &str, limit: usize) -> Result<Vec<u64>, String> {
+        let requester = requester.to_owned();
+        self.with_conn(move |conn| {
+            let mut statement = conn.prepare("SELECT Sentence FROM SentenceHistory WHERE Requester=?1 ORDER BY ID DESC LIMIT ?2")?;
+            let rows = statement.query_map(params![requester, limit as u64], |row| row.get(0))?;
+            rows.collect()
+        }).await
+    }
+
+    async fn record_history(&self, requester: &str, ids: &[u64], keep: usize) -> Result<(), String> {
+        let requester = requester.to_owned();
+        let ids = ids.to_vec();
+        self.with_conn(move |conn| {
+            let mut statement = conn.prepare("INSERT INTO SentenceHistory (Requester, Sentence) VALUES (?1, ?2)")?;
+            for id in ids {
+                statement.execute(params![requester, id])?;
+            }
+            conn.execute(
+                "DELETE FROM SentenceHistory WHERE Requester=?1 AND ID NOT IN
+                    (SELECT ID FROM SentenceHistory WHERE Requester=?1 ORDER BY ID DESC LIMIT ?2)",
+                params![requester, keep as u64]
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn add_submission(&self, submission: &Submission) -> Result<u64, String> {
+        let data = serde_json::to_string(submission).map_err(|error| error.to_string())?;
+        let status = submission.status.name();
+        self.with_conn(move |conn| {
+            conn.execute("INSERT INTO Submissions (Status, Data) VALUES (?1, ?2)", params![status, data])?;
+            Ok(conn.last_insert_rowid() as u64)
+        }).await
+    }
+
+    async fn update_submission(&self, submission: &Submission, from: SubmissionStatus) -> Result<bool, String> {
+        let data = serde_json::to_string(submission).map_err(|error| error.to_string())?;
+        let status = submission.status.name();
+        let id = submission.id;
+        self.with_conn(move |conn| {
+            let updated = conn.execute("UPDATE Submissions SET Status=?1, Data=?2 WHERE ID=?3 AND Status=?4", params![status, data, id, from.name()])?;
+            Ok(updated > 0)
+        }).await
+    }
+
+    async fn submission(&self, id: u64) -> Result<Option<Submission>, String> {
+        let row: Option<(u64, String)> = self.with_conn(move |conn| {
+            conn.query_row("SELECT ID, Data FROM Submissions WHERE ID=?1", params![id], |row| Ok((row.get(0)?, row.get(1)?))).optional()
+        }).await?;
+        row.map(submission_from_row).transpose()
+    }
+
+    async fn submissions(&self, status: Option<SubmissionStatus>) -> Result<Vec<Submission>, String> {
+        let rows: Vec<(u64, String)> = self.with_conn(move |conn| {
+            // A missing status matches everything
+            let mut statement = conn.prepare("SELECT ID, Data FROM Submissions WHERE ?1 IS NULL OR Status=?1 ORDER BY ID")?;
+            let rows = statement.query_map(params![status.map(|status| status.name())], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect()
+        }).await?;
+        rows.into_iter().map(submission_from_row).collect()
+    }
+
+    async fn guild_filter(&self, guild: &str) -> Result<Option<TagFilter>, String> {
+        let guild = guild.to_owned();
+        let filter: Option<String> = self.with_conn(move |conn| {
+            conn.query_row("SELECT Filter FROM GuildFilters WHERE Guild=?1", params![guild], |row| row.get(0)).optional()
+        }).await?;
+        match filter {
+            Some(filter) => serde_json::from_str(&filter).map(Some).map_err(|error| error.to_string()),
+            None => Ok(None)
+        }
+    }
+
+    async fn set_guild_filter(&self, guild: &str, filter: Option<&TagFilter>) -> Result<(), String> {
+        let guild = guild.to_owned();
+        let filter = match filter {
+            Some(filter) => Some(serde_json::to_string(filter).map_err(|error| error.to_string())?),
+            None => None
+        };
+        self.with_conn(move |conn| {
+            match filter {
+                Some(filter) => conn.execute("REPLACE INTO GuildFilters (Guild, Filter) VALUES (?1, ?2)", params![guild, filter]),
+                None => conn.execute("DELETE FROM GuildFilters WHERE Guild=?1", params![guild])
+            }.map(|_| ())
+        }).await
+    }
+
+    async fn packs(&self) -> Result<Vec<PackManifest>, String> {
+        let manifests: Vec<String> = self.with_conn(|conn| {
+            let mut statement = conn.prepare("SELECT Manifest FROM Packs ORDER BY Name")?;
+            let rows = statement.query_map([], |row| row.get(0))?;
+            rows.collect()
+        }).await?;
+        manifests.iter().map(|manifest| serde_json::from_str(manifest).map_err(|error| error.to_string())).collect()
+    }
+
+    async fn save_pack(&self, manifest: &PackManifest) -> Result<(), String> {
+        let name = manifest.name.clone();
+        let data = serde_json::to_string(manifest).map_err(|error| error.to_string())?;
+        self.with_conn(move |conn| {
+            conn.execute("REPLACE INTO Packs (Name, Manifest) VALUES (?1, ?2)", params![name, data]).map(|_| ())
+        }).await
+    }
+
+    async fn remove_pack(&self, name: &str) -> Result<bool, String> {
+        let name = name.to_owned();
+        self.with_conn(move |conn| {
+            let removed = conn.execute("DELETE FROM Packs WHERE Name=?1", params![name])? > 0;
+            conn.execute("DELETE FROM SentenceTags WHERE Sentence IN (SELECT Sentence FROM PackSentences WHERE Pack=?1)", params![name])?;
+            conn.execute("DELETE FROM SentenceLanguages WHERE Sentence IN (SELECT Sentence FROM PackSentences WHERE Pack=?1)", params![name])?;
+            conn.execute("DELETE FROM Sentences WHERE ID IN (SELECT Sentence FROM PackSentences WHERE Pack=?1)", params![name])?;
+            conn.execute("DELETE FROM PackSentences WHERE Pack=?1", params![name])?;
+            Ok(removed)
+        }).await
+    }
+
+    async fn guild_packs(&self, guild: &str) -> Result<Option<Vec<String>>, String> {
+        let guild = guild.to_owned();
+        let packs: Option<String> = self.with_conn(move |conn| {
+            conn.query_row("SELECT Packs FROM GuildPacks WHERE Guild=?1", params![guild], |row| row.get(0)).optional()
+        }).await?;
+        match packs {
+            Some(packs) => serde_json::from_str(&packs).map(Some).map_err(|error| error.to_string()),
+            None => Ok(None)
+        }
+    }
+
+    async fn set_guild_packs(&self, guild: &str, packs: Option<&[String]>) -> Result<(), String> {
+        let guild = guild.to_owned();
+        let packs = match packs {
+            Some(packs) => Some(serde_json::to_string(packs).map_err(|error| error.to_string())?),
+            None => None
+        };
+        self.with_conn(move |conn| {
+            match packs {
+                Some(packs) => conn.execute("REPLACE INTO GuildPacks (Guild, Packs) VALUES (?1, ?2)", params![guild, packs]),
+                None => conn.execute("DELETE FROM GuildPacks WHERE Guild=?1", params![guild])
+            }.map(|_| ())
+        }).await
+    }
+}
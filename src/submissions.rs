@@ -0,0 +1,214 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::locale::{default_language, normalise_language};
+use crate::protocol::DispatchError;
+use crate::sentences::{SentenceType, lint_template, normalise_tag};
+use crate::storage::{Sentence, SentenceStore};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionStatus {
+    Pending,
+    Approved,
+    Rejected
+}
+
+impl SubmissionStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SubmissionStatus::Pending => "pending",
+            SubmissionStatus::Approved => "approved",
+            SubmissionStatus::Rejected => "rejected"
+        }
+    }
+}
+
+/// Something that happened to a submission, kept so its history can be seen
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "action")]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionEvent {
+    Submitted {
+        by: String,
+        at: i64
+    },
+    Edited {
+        by: String,
+        at: i64,
+        /// The text before the edit
+        previous: String
+    },
+    Approved {
+        by: String,
+        at: i64
+    },
+    Rejected {
+        by: String,
+        at: i64,
+        reason: String
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Submission {
+    pub id: u64,
+    pub text: String,
+    pub sentence_type: SentenceType,
+    pub tags: Vec<String>,
+    #[serde(default = "default_language")]
+    pub language: String,
+    pub submitter: String,
+    pub status: SubmissionStatus,
+    /// The sentence it became once approved, for attribution
+    pub sentence: Option<u64>,
+    pub history: Vec<SubmissionEvent>
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "action")]
+#[serde(rename_all = "snake_case")]
+pub enum Review {
+    /// Approving can fix the text up at the same time
+    Approve {
+        text: Option<String>
+    },
+    Edit {
+        text: String
+    },
+    Reject {
+        reason: String
+    }
+}
+
+fn normalise_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut normalised: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = normalise_tag(tag)?;
+        if !normalised.contains(&tag) {
+            normalised.push(tag);
+        }
+    }
+    Ok(normalised)
+}
+
+/// Lints the template and puts it in the queue for moderators
+pub async fn submit(store: &dyn SentenceStore, text: &str, tags: &[String], language: &str, submitter: &str) -> Result<Submission, DispatchError> {
+    let text = text.trim();
+    let sentence_type = lint_template(text).map_err(DispatchError::Invalid)?;
+    let mut submission = Submission {
+        id: 0,
+        text: text.to_owned(),
+        sentence_type,
+        tags: normalise_tags(tags).map_err(DispatchError::Invalid)?,
+        language: normalise_language(language).map_err(DispatchError::Invalid)?,
+        submitter: submitter.to_owned(),
+        status: SubmissionStatus::Pending,
+        sentence: None,
+        history: vec![SubmissionEvent::Submitted { by: submitter.to_owned(), at: Utc::now().timestamp() }]
+    };
+    submission.id = store.add_submission(&submission).await.map_err(DispatchError::Unavailable)?;
+    Ok(submission)
+}
+
+pub async fn review(store: &dyn SentenceStore, id: u64, moderator: &str, review: Review) -> Result<Submission, DispatchError> {
+    let mut submission = match store.submission(id).await.map_err(DispatchError::Unavailable)? {
+        Some(submission) => submission,
+        None => return Err(DispatchError::Invalid(format!("There's no submission with ID {id}.")))
+    };
+    if submission.status != SubmissionStatus::Pending {
+        return Err(DispatchError::Invalid(format!("Submission {id} has already been {}.", submission.status.name())));
+    }
+    let pending = submission.clone();
+
+    let now = Utc::now().timestamp();
+    let by = moderator.to_owned();
+    let new_text = match &review {
+        Review::Approve { text } => text.clone(),
+        Review::Edit { text } => Some(text.clone()),
+        Review::Reject { .. } => None
+    };
+    if let Some(text) = new_text {
+        let text = text.trim().to_owned();
+        submission.sentence_type = lint_template(&text).map_err(DispatchError::Invalid)?;
+        let previous = std::mem::replace(&mut submission.text, text);
+        submission.history.push(SubmissionEvent::Edited { by: by.clone(), at: now, previous });
+    }
+
+    match review {
+        Review::Approve { .. } => {
+            submission.status = SubmissionStatus::Approved;
+            submission.history.push(SubmissionEvent::Approved { by, at: now });
+            // Claimed before the sentence is added, so two moderators
+            // approving at once can't both add it
+            claim(store, &submission, SubmissionStatus::Pending).await?;
+            let added = store.add(&Sentence {
+                id: 0,
+                sentence_type: submission.sentence_type,
+                text: submission.text.clone(),
+                tags: submission.tags.clone(),
+                pack: None,
+                language: submission.language.clone(),
+                submitter: Some(submission.submitter.clone())
+            }).await;
+            match added {
+                Ok(sentence) => submission.sentence = Some(sentence),
+                Err(error) => {
+                    // Back in the queue so it can be approved again
+                    store.update_submission(&pending, SubmissionStatus::Approved).await.map_err(DispatchError::Unavailable)?;
+                    return Err(DispatchError::Unavailable(error));
+                }
+            }
+            claim(store, &submission, SubmissionStatus::Approved).await?;
+        }
+        Review::Edit { .. } => claim(store, &submission, SubmissionStatus::Pending).await?,
+        Review::Reject { reason } => {
+            if reason.trim().is_empty() {
+                return Err(DispatchError::Invalid("Rejections need a reason.".to_owned()));
+            }
+            submission.status = SubmissionStatus::Rejected;
+            submission.history.push(SubmissionEvent::Rejected { by, at: now, reason });
+            claim(store, &submission, SubmissionStatus::Pending).await?;
+        }
+    }
+    Ok(submission)
+}
+
+// Saves the submission if nobody else has reviewed it in the meantime
+async fn claim(store: &dyn SentenceStore, submission: &Submission, from: SubmissionStatus) -> Result<(), DispatchError> {
+    match store.update_submission(submission, from).await.map_err(DispatchError::Unavailable)? {
+        true => Ok(()),
+        false => Err(DispatchError::Invalid(format!("Submission {} was reviewed by someone else first.", submission.id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn approving_credits_the_submitter() {
+        let store = MemoryStore::new();
+        let submission = submit(&store, "[name] waved at [objective].", &[], "en", "ada").await.unwrap();
+        let approved = review(&store, submission.id, "mod", Review::Approve { text: None }).await.unwrap();
+        let sentence = store.list().await.unwrap().into_iter().find(|sentence| Some(sentence.id) == approved.sentence).unwrap();
+        assert_eq!(sentence.submitter.as_deref(), Some("ada"));
+    }
+
+    #[tokio::test]
+    async fn reviews_only_happen_once() {
+        let store = MemoryStore::new();
+        let submission = submit(&store, "[name] waved at [objective].", &[], "en", "ada").await.unwrap();
+        review(&store, submission.id, "mod", Review::Approve { text: None }).await.unwrap();
+        assert!(review(&store, submission.id, "mod", Review::Approve { text: None }).await.is_err());
+        assert_eq!(store.list().await.unwrap().len(), 1);
+
+        // Someone else getting there between reading it and saving it
+        let submission = submit(&store, "[name] waved at [objective].", &[], "en", "ada").await.unwrap();
+        let mut rejected = submission.clone();
+        rejected.status = SubmissionStatus::Rejected;
+        assert!(store.update_submission(&rejected, SubmissionStatus::Pending).await.unwrap());
+        assert!(!store.update_submission(&submission, SubmissionStatus::Pending).await.unwrap());
+    }
+}